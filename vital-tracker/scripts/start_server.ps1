@@ -45,7 +45,9 @@ if (Test-Path $exePath) {
     # Start via cmd.exe so we can redirect stdout/stderr to the log file on PowerShell 5.1
     $exeQuoted = '"' + $exePath + '"'
     $logQuoted = '"' + $logPath + '"'
-    $cmd = "$exeQuoted >> $logQuoted 2>&1"
+    # Keep using the repo's data folder rather than the per-user default
+    $dataQuoted = '"' + (Join-Path $repoRoot 'data') + '"'
+    $cmd = "$exeQuoted --data-root $dataQuoted >> $logQuoted 2>&1"
     $proc = Start-Process -FilePath 'cmd.exe' -ArgumentList '/c', $cmd -WorkingDirectory $repoRoot -WindowStyle Hidden -PassThru
 
     if ($proc) {
@@ -0,0 +1,34 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+use std::borrow::Cow;
+use std::path::Path;
+use tokio::fs;
+
+use crate::paths;
+
+/// The web UI, compiled into the binary so it is found whatever the working directory.
+#[derive(RustEmbed)]
+#[folder = "static/"]
+struct Embedded;
+
+/// Serves `name` from `override_dir` when one is configured (handy while editing the UI),
+/// otherwise from the copy embedded at build time.
+pub async fn serve(override_dir: Option<&Path>, name: &str) -> Response {
+    if !paths::is_contained(name) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let bytes: Cow<'static, [u8]> = match override_dir {
+        Some(dir) => match fs::read(dir.join(name)).await {
+            Ok(b) => Cow::Owned(b),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return StatusCode::NOT_FOUND.into_response(),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Unhandled internal error: {}", e)).into_response(),
+        },
+        None => match Embedded::get(name) {
+            Some(f) => f.data,
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+    };
+    let mime = mime_guess::from_path(name).first_or_octet_stream();
+    ([(header::CONTENT_TYPE, mime.essence_str().to_string())], bytes.into_owned()).into_response()
+}
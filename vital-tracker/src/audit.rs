@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::sync::Mutex;
+
+use crate::blobstore::hash_bytes;
+use crate::crypto;
+use crate::paths;
+
+/// `prev_hash` of the first record.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Lines written while encryption at rest is on are hex-encoded ciphertext behind this prefix.
+const ENCRYPTED_PREFIX: &str = "enc:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    Edit,
+    Delete,
+    Restore,
+    Import,
+    TokenCreate,
+    TokenRevoke,
+    /// Recorded at most once a minute per token, together with its `last_used` time
+    TokenUse,
+    /// A wrong password on the login form; attempts refused during a lockout are not recorded
+    LoginFailed,
+}
+
+/// One line of the audit log. `hash` covers every other field, including `prev_hash`, so
+/// editing or removing a record breaks the chain from there on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub seq: u64,
+    pub time: DateTime<Utc>,
+    /// `session`, `token:<name> (<id>)`, `cli:<user>`, `client:<address>` for failed logins,
+    /// or `anonymous` when auth is off
+    pub actor: String,
+    pub action: Action,
+    /// Profile the entry belongs to; absent on token records and on records from before profiles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+    /// Photo name -> blob hash released by a delete, so a restore can link the photos again
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub photos: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub prev_hash: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+impl Record {
+    fn digest(&self) -> Result<String> {
+        let unhashed = Record { hash: String::new(), ..self.clone() };
+        Ok(hash_bytes(&serde_json::to_vec(&unhashed)?))
+    }
+
+    /// Whether the record concerns `profile`; records without one belong to the default profile.
+    pub fn is_for_profile(&self, profile: &str) -> bool {
+        self.profile.as_deref().unwrap_or(paths::DEFAULT_PROFILE) == profile
+    }
+}
+
+/// What happened, before the log assigns it a place in the chain.
+#[derive(Debug, Default)]
+pub struct Event {
+    action: Option<Action>,
+    profile: Option<String>,
+    entry_id: Option<String>,
+    before: Option<Value>,
+    after: Option<Value>,
+    photos: BTreeMap<String, String>,
+    detail: Option<String>,
+}
+
+impl Event {
+    pub fn new(action: Action) -> Self {
+        Event { action: Some(action), ..Default::default() }
+    }
+
+    pub fn profile(mut self, id: impl Into<String>) -> Self {
+        self.profile = Some(id.into());
+        self
+    }
+
+    pub fn entry(mut self, id: impl Into<String>) -> Self {
+        self.entry_id = Some(id.into());
+        self
+    }
+
+    pub fn before(mut self, value: &impl Serialize) -> Self {
+        self.before = serde_json::to_value(value).ok();
+        self
+    }
+
+    pub fn after(mut self, value: &impl Serialize) -> Self {
+        self.after = serde_json::to_value(value).ok();
+        self
+    }
+
+    pub fn photos(mut self, photos: BTreeMap<String, String>) -> Self {
+        self.photos = photos;
+        self
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// Sequence number and hash of the last record, and the file length they were read at, so
+/// appends by another process (the command line next to the server) are noticed.
+struct Tail {
+    seq: u64,
+    hash: String,
+    len: u64,
+}
+
+static TAIL: Mutex<Option<Tail>> = Mutex::new(None);
+
+/// Actor name for changes made from the command line.
+pub fn cli_actor() -> String {
+    let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default();
+    if user.is_empty() {
+        "cli".to_string()
+    } else {
+        format!("cli:{}", user)
+    }
+}
+
+fn encode_line(record: &Record) -> Result<String> {
+    let json = serde_json::to_vec(record)?;
+    let sealed = crypto::seal(json.clone())?;
+    if sealed == json {
+        Ok(String::from_utf8(json)?)
+    } else {
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, hex::encode(sealed)))
+    }
+}
+
+fn decode_line(line: &str) -> Result<Record> {
+    let json = match line.strip_prefix(ENCRYPTED_PREFIX) {
+        Some(h) => crypto::open(hex::decode(h)?)?,
+        None => line.as_bytes().to_vec(),
+    };
+    Ok(serde_json::from_slice(&json)?)
+}
+
+fn last_record(file: &mut std::fs::File) -> Result<Option<Record>> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut last = None;
+    for line in BufReader::new(&mut *file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    last.map(|l| decode_line(&l)).transpose()
+}
+
+fn append(actor: &str, event: Event) -> Result<Record> {
+    let mut file = std::fs::OpenOptions::new().create(true).read(true).append(true).open(paths::audit_log_path())?;
+    file.lock_exclusive()?;
+    let result = (|| {
+        let mut tail = TAIL.lock().unwrap_or_else(|e| e.into_inner());
+        let len = file.metadata()?.len();
+        if tail.as_ref().map(|t| t.len) != Some(len) {
+            *tail = Some(match last_record(&mut file)? {
+                Some(r) => Tail { seq: r.seq, hash: r.hash, len },
+                None => Tail { seq: 0, hash: GENESIS.to_string(), len },
+            });
+        }
+        let prev = tail.as_ref().expect("tail loaded above");
+        let mut record = Record {
+            seq: prev.seq + 1,
+            time: Utc::now(),
+            actor: actor.to_string(),
+            action: event.action.ok_or_else(|| anyhow!("audit event without an action"))?,
+            profile: event.profile,
+            entry_id: event.entry_id,
+            before: event.before,
+            after: event.after,
+            photos: event.photos,
+            detail: event.detail,
+            prev_hash: prev.hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = record.digest()?;
+        file.write_all(format!("{}\n", encode_line(&record)?).as_bytes())?;
+        file.sync_data()?;
+        *tail = Some(Tail { seq: record.seq, hash: record.hash.clone(), len: file.metadata()?.len() });
+        Ok(record)
+    })();
+    let _ = file.unlock();
+    result
+}
+
+/// Appends an event to the audit log. The change it describes has already happened, so a
+/// failure is logged rather than returned.
+pub async fn record(actor: &str, event: Event) {
+    let actor = actor.to_string();
+    let action = event.action;
+    match tokio::task::spawn_blocking(move || append(&actor, event)).await {
+        Ok(Ok(r)) => tracing::debug!(seq = r.seq, action = ?r.action, "audit record written"),
+        Ok(Err(e)) => tracing::error!(action = ?action, "could not write audit record: {:#}", e),
+        Err(e) => tracing::error!(action = ?action, "could not write audit record: {}", e),
+    }
+}
+
+/// Rewrites every line with the current encryption setting, for `encrypt` and `decrypt`.
+/// Hashes cover the plaintext records, so the chain is unaffected.
+pub async fn reencode() -> Result<usize> {
+    tokio::task::spawn_blocking(|| -> Result<usize> {
+        let path = paths::audit_log_path();
+        let file = match std::fs::OpenOptions::new().read(true).append(true).open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        file.lock_exclusive()?;
+        let result = (|| {
+            let mut out = String::new();
+            let mut count = 0;
+            for (i, line) in BufReader::new(&file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let record = decode_line(&line).with_context(|| format!("audit log line {}", i + 1))?;
+                out.push_str(&encode_line(&record)?);
+                out.push('\n');
+                count += 1;
+            }
+            let tmp = path.with_extension("jsonl.tmp");
+            std::fs::write(&tmp, out)?;
+            std::fs::rename(&tmp, &path)?;
+            Ok(count)
+        })();
+        let _ = file.unlock();
+        result
+    })
+    .await?
+}
+
+/// Every record, oldest first.
+pub async fn read_all() -> Result<Vec<Record>> {
+    let text = match tokio::fs::read_to_string(paths::audit_log_path()).await {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| decode_line(l).with_context(|| format!("audit log line {}", i + 1)))
+        .collect()
+}
+
+/// The most recent delete of an entry, whose `before` holds what a restore brings back.
+pub async fn last_delete(profile: &str, entry_id: &str) -> Result<Option<Record>> {
+    Ok(read_all()
+        .await?
+        .into_iter()
+        .rev()
+        .find(|r| r.action == Action::Delete && r.is_for_profile(profile) && r.entry_id.as_deref() == Some(entry_id)))
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ChainReport {
+    pub records: u64,
+    /// Hash of the last record; note it down to detect records cut off the end later
+    pub head: String,
+    /// First problem found, with its line number
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem: Option<String>,
+}
+
+impl ChainReport {
+    pub fn is_intact(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+/// Recomputes every record's hash and checks the sequence and links between them.
+pub async fn verify() -> Result<ChainReport> {
+    let text = match tokio::fs::read_to_string(paths::audit_log_path()).await {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut report = ChainReport { head: GENESIS.to_string(), ..Default::default() };
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let problem = match decode_line(line) {
+            Err(e) => Some(format!("{:#}", e)),
+            Ok(r) if r.seq != report.records + 1 => Some(format!("sequence number {} where {} was expected", r.seq, report.records + 1)),
+            Ok(r) if r.prev_hash != report.head => Some("does not link to the previous record".to_string()),
+            Ok(r) => match r.digest() {
+                Ok(h) if h == r.hash => {
+                    report.records = r.seq;
+                    report.head = r.hash;
+                    None
+                }
+                Ok(_) => Some("hash does not match the record's contents".to_string()),
+                Err(e) => Some(e.to_string()),
+            },
+        };
+        if let Some(p) = problem {
+            report.problem = Some(format!("line {}: {}", i + 1, p));
+            break;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines() -> Vec<String> {
+        std::fs::read_to_string(paths::audit_log_path()).unwrap().lines().map(str::to_string).collect()
+    }
+
+    fn rewrite(lines: &[String]) {
+        std::fs::write(paths::audit_log_path(), lines.join("\n") + "\n").unwrap();
+    }
+
+    fn create(id: &str) -> Event {
+        Event::new(Action::Create).profile("default").entry(id).after(&serde_json::json!({ "sys": 120, "dia": 80 }))
+    }
+
+    // One test, as the data root, key and audit tail are process-wide
+    #[tokio::test]
+    async fn hash_chain() {
+        let dir = std::env::temp_dir().join(format!("vital-tracker-audit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        paths::init(&dir).unwrap();
+
+        // Plain records chain from the genesis hash
+        assert_eq!(verify().await.unwrap().head, GENESIS);
+        let first = append("cli:test", create("1")).unwrap();
+        let second = append("cli:test", create("2")).unwrap();
+        assert_eq!(first.prev_hash, GENESIS);
+        assert_eq!(second.prev_hash, first.hash);
+        let report = verify().await.unwrap();
+        assert!(report.is_intact(), "{:?}", report.problem);
+        assert_eq!((report.records, report.head.as_str()), (2, second.hash.as_str()));
+
+        // Encrypted lines verify like plain ones, and re-encoding leaves the chain alone
+        crypto::init_for_tests(b"audit test passphrase");
+        crypto::set_encrypt_writes_for_tests(true);
+        let third = append("cli:test", Event::new(Action::Delete).profile("default").entry("2")).unwrap();
+        assert!(lines()[2].starts_with(ENCRYPTED_PREFIX) && !lines()[1].starts_with(ENCRYPTED_PREFIX));
+        assert_eq!(verify().await.unwrap().head, third.hash);
+        assert_eq!(reencode().await.unwrap(), 3);
+        assert!(lines().iter().all(|l| l.starts_with(ENCRYPTED_PREFIX)));
+        let report = verify().await.unwrap();
+        assert!(report.is_intact(), "{:?}", report.problem);
+        assert_eq!(report.head, third.hash);
+        assert_eq!(last_delete("default", "2").await.unwrap().map(|r| r.seq), Some(3));
+        let encrypted = lines();
+
+        crypto::set_encrypt_writes_for_tests(false);
+        assert_eq!(reencode().await.unwrap(), 3);
+        let plain = lines();
+        assert!(plain.iter().all(|l| l.starts_with('{')));
+        assert_eq!(verify().await.unwrap().head, third.hash);
+
+        // Editing a record breaks its hash
+        let mut edited = plain.clone();
+        edited[1] = edited[1].replace("cli:test", "cli:evil");
+        rewrite(&edited);
+        assert_eq!(verify().await.unwrap().problem.as_deref(), Some("line 2: hash does not match the record's contents"));
+
+        // Removing one breaks the sequence
+        rewrite(&[plain[0].clone(), plain[2].clone()]);
+        assert_eq!(verify().await.unwrap().problem.as_deref(), Some("line 2: sequence number 3 where 2 was expected"));
+
+        // A damaged encrypted line no longer decrypts
+        let mut damaged = encrypted.clone();
+        let flip = if damaged[0].ends_with('0') { '1' } else { '0' };
+        damaged[0].pop();
+        damaged[0].push(flip);
+        rewrite(&damaged);
+        assert!(verify().await.unwrap().problem.unwrap().starts_with("line 1: decryption failed"));
+
+        rewrite(&encrypted);
+        assert!(verify().await.unwrap().is_intact());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,501 @@
+use anyhow::{anyhow, Result};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio::fs;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::audit::{self, Action, Event};
+use crate::blobstore::hash_bytes;
+use crate::paths;
+
+pub const SESSION_COOKIE: &str = "vital_session";
+const TOKEN_PREFIX: &str = "vt_";
+/// `last_used` is written back at most this often per token
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+/// Login attempts a client gets before it has to wait between tries
+const FREE_LOGIN_ATTEMPTS: u32 = 5;
+/// Longest wait between login attempts; the wait doubles with every attempt up to this
+const MAX_LOGIN_BACKOFF: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+/// A client's attempts are forgotten after this long without one
+const LOGIN_ATTEMPTS_RESET: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Clients tracked before forgotten ones are dropped
+const MAX_LOGIN_CLIENTS: usize = 1024;
+
+/// What an API token may do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Reading entries, photos, charts and metrics
+    Read,
+    /// Also adding and deleting entries
+    Write,
+    /// Also token management and blob maintenance
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// Tokens created before scopes existed had full access
+fn legacy_scope() -> Scope {
+    Scope::Admin
+}
+
+/// The scope a request needs: admin for token management, blob maintenance and the audit
+/// log, read for other GETs, write for everything else.
+pub fn required_scope(method: &Method, path: &str) -> Scope {
+    if path.starts_with("/api/v1/tokens") || path.starts_with("/blobs/") || path.starts_with("/audit") {
+        Scope::Admin
+    } else if method == Method::GET || method == Method::HEAD {
+        Scope::Read
+    } else {
+        Scope::Write
+    }
+}
+
+/// Who made a request; added to the request extensions by [`require_auth`].
+#[derive(Debug, Clone)]
+pub enum Principal {
+    /// Logged in through the web UI
+    Session,
+    Token { id: String, name: String, scope: Scope },
+}
+
+impl Principal {
+    /// Short description for logs, e.g. `token:cron (3f2a9c01b7de)`.
+    pub fn label(&self) -> String {
+        match self {
+            Principal::Session => "session".to_string(),
+            Principal::Token { id, name, .. } => format!("token:{} ({})", name, id),
+        }
+    }
+
+    /// UI sessions can do everything the password holder can.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Principal::Session => Scope::Admin,
+            Principal::Token { scope, .. } => *scope,
+        }
+    }
+}
+
+/// An API token as listed to users; never includes the secret or its hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub name: String,
+    #[serde(default = "legacy_scope")]
+    pub scope: Scope,
+    pub created: DateTime<Utc>,
+    #[serde(default)]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl TokenInfo {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|e| e <= now)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiToken {
+    #[serde(flatten)]
+    info: TokenInfo,
+    /// SHA-256 of the secret; the secret itself is shown once, at creation
+    hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Session {
+    hash: String,
+    expires: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AuthData {
+    /// Argon2 PHC string
+    password_hash: Option<String>,
+    #[serde(default)]
+    tokens: Vec<ApiToken>,
+    #[serde(default)]
+    sessions: Vec<Session>,
+}
+
+struct LoadedAuth {
+    data: AuthData,
+    modified: Option<SystemTime>,
+}
+
+/// The UI password, login sessions and API tokens, kept in `auth.json` in the data root.
+/// Only hashes are stored.
+pub struct AuthStore {
+    state: Mutex<LoadedAuth>,
+    session_ttl: Duration,
+}
+
+fn random_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hash_secret(secret: &str) -> String {
+    hash_bytes(secret.as_bytes())
+}
+
+impl AuthStore {
+    pub async fn open(session_ttl_hours: u64) -> Result<Self> {
+        Ok(AuthStore { state: Mutex::new(Self::load().await?), session_ttl: Duration::hours(session_ttl_hours as i64) })
+    }
+
+    async fn file_modified() -> Option<SystemTime> {
+        fs::metadata(paths::auth_path()).await.ok()?.modified().ok()
+    }
+
+    async fn load() -> Result<LoadedAuth> {
+        let modified = Self::file_modified().await;
+        let data = match fs::read_to_string(paths::auth_path()).await {
+            Ok(j) => serde_json::from_str(&j).map_err(|e| anyhow!("corrupt {}: {}", paths::auth_path().display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => AuthData::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(LoadedAuth { data, modified })
+    }
+
+    /// Locks the data, re-reading the file if the command line has changed it meanwhile.
+    async fn lock(&self) -> MutexGuard<'_, LoadedAuth> {
+        let mut state = self.state.lock().await;
+        if Self::file_modified().await != state.modified {
+            match Self::load().await {
+                Ok(fresh) => *state = fresh,
+                Err(e) => tracing::warn!(error = %e, "could not reload auth data"),
+            }
+        }
+        state
+    }
+
+    async fn persist(state: &mut LoadedAuth) -> Result<()> {
+        let path = paths::auth_path();
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&state.data)?).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        fs::rename(&tmp, &path).await?;
+        state.modified = Self::file_modified().await;
+        Ok(())
+    }
+
+    pub async fn has_password(&self) -> bool {
+        self.lock().await.data.password_hash.is_some()
+    }
+
+    /// Replaces the password and ends every existing session.
+    pub async fn set_password(&self, password: &str) -> Result<()> {
+        let password = password.to_string();
+        let hash = tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default().hash_password(password.as_bytes(), &salt).map(|h| h.to_string()).map_err(|e| anyhow!("hashing password: {}", e))
+        })
+        .await??;
+        let mut state = self.lock().await;
+        state.data.password_hash = Some(hash);
+        state.data.sessions.clear();
+        Self::persist(&mut state).await
+    }
+
+    /// Checks the password and starts a session, returning its cookie value.
+    pub async fn login(&self, password: &str) -> Result<Option<String>> {
+        let Some(stored) = self.lock().await.data.password_hash.clone() else { return Ok(None) };
+        let password = password.to_string();
+        let ok = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&stored).map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+        })
+        .await?
+        .map_err(|e| anyhow!("stored password hash is invalid: {}", e))?;
+        if !ok {
+            return Ok(None);
+        }
+        let secret = random_secret();
+        let mut state = self.lock().await;
+        let now = Utc::now();
+        state.data.sessions.retain(|s| s.expires > now);
+        state.data.sessions.push(Session { hash: hash_secret(&secret), expires: now + self.session_ttl });
+        Self::persist(&mut state).await?;
+        Ok(Some(secret))
+    }
+
+    pub async fn logout(&self, secret: &str) -> Result<()> {
+        let hash = hash_secret(secret);
+        let mut state = self.lock().await;
+        let before = state.data.sessions.len();
+        state.data.sessions.retain(|s| s.hash != hash);
+        if state.data.sessions.len() != before {
+            Self::persist(&mut state).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn session_valid(&self, secret: &str) -> bool {
+        let hash = hash_secret(secret);
+        let now = Utc::now();
+        self.lock().await.data.sessions.iter().any(|s| s.hash == hash && s.expires > now)
+    }
+
+    pub fn session_ttl_secs(&self) -> i64 {
+        self.session_ttl.num_seconds()
+    }
+
+    /// Creates an API token; the returned secret is not stored and cannot be shown again.
+    pub async fn create_token(&self, name: &str, scope: Scope, valid_for: Option<Duration>) -> Result<(TokenInfo, String)> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("token name must not be empty"));
+        }
+        let secret = format!("{}{}", TOKEN_PREFIX, random_secret());
+        let now = Utc::now();
+        let expires = match valid_for {
+            Some(d) => Some(now.checked_add_signed(d).ok_or_else(|| anyhow!("token expiry is too far in the future"))?),
+            None => None,
+        };
+        let info = TokenInfo {
+            id: random_secret()[..12].to_string(),
+            name: name.to_string(),
+            scope,
+            created: now,
+            expires,
+            last_used: None,
+        };
+        let mut state = self.lock().await;
+        state.data.tokens.push(ApiToken { info: info.clone(), hash: hash_secret(&secret) });
+        Self::persist(&mut state).await?;
+        Ok((info, secret))
+    }
+
+    pub async fn list_tokens(&self) -> Vec<TokenInfo> {
+        self.lock().await.data.tokens.iter().map(|t| t.info.clone()).collect()
+    }
+
+    /// Deletes the token with this id; `false` if there is none.
+    pub async fn revoke_token(&self, id: &str) -> Result<bool> {
+        let mut state = self.lock().await;
+        let before = state.data.tokens.len();
+        state.data.tokens.retain(|t| t.info.id != id);
+        if state.data.tokens.len() == before {
+            return Ok(false);
+        }
+        Self::persist(&mut state).await?;
+        Ok(true)
+    }
+
+    /// Looks up an unexpired token by its secret and records the use.
+    pub async fn use_token(&self, secret: &str) -> Option<TokenInfo> {
+        let hash = hash_secret(secret);
+        let now = Utc::now();
+        let mut state = self.lock().await;
+        let token = state.data.tokens.iter_mut().find(|t| t.hash == hash)?;
+        if token.info.is_expired(now) {
+            return None;
+        }
+        let stale = token.info.last_used.is_none_or(|t| (now - t).num_seconds() >= LAST_USED_RESOLUTION_SECS);
+        if stale {
+            token.info.last_used = Some(now);
+        }
+        let info = token.info.clone();
+        if stale {
+            if let Err(e) = Self::persist(&mut state).await {
+                tracing::warn!(error = %e, "could not record token use");
+            }
+            drop(state);
+            let actor = Principal::Token { id: info.id.clone(), name: info.name.clone(), scope: info.scope }.label();
+            audit::record(&actor, Event::new(Action::TokenUse)).await;
+        }
+        Some(info)
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+pub fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='))
+}
+
+/// Lets a request through with a valid bearer token or session cookie whose scope covers
+/// it. Browsers asking for a page are sent to the login form; everything else gets a 401,
+/// or a 403 when the token's scope is too narrow.
+pub async fn require_auth<B>(State(auth): State<Arc<AuthStore>>, mut req: Request<B>, next: Next<B>) -> Response {
+    let principal = if let Some(secret) = bearer_token(req.headers()) {
+        auth.use_token(secret).await.map(|t| Principal::Token { id: t.id, name: t.name, scope: t.scope })
+    } else if let Some(secret) = session_cookie(req.headers()) {
+        auth.session_valid(secret).await.then_some(Principal::Session)
+    } else {
+        None
+    };
+    let Some(principal) = principal else {
+        let wants_page = req.method() == Method::GET
+            && req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok()).is_some_and(|a| a.contains("text/html"));
+        if wants_page {
+            return Redirect::to("/login").into_response();
+        }
+        return (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Bearer realm=\"vital-tracker\"")], "authentication required").into_response();
+    };
+    let needed = required_scope(req.method(), req.uri().path());
+    if principal.scope() < needed {
+        tracing::warn!(actor = %principal.label(), needed = needed.as_str(), "token scope too narrow");
+        return (StatusCode::FORBIDDEN, format!("this request needs a token with the '{}' scope", needed.as_str())).into_response();
+    }
+    tracing::debug!(actor = %principal.label(), "authenticated");
+    req.extensions_mut().insert(principal);
+    next.run(req).await
+}
+
+struct LoginAttempts {
+    count: u32,
+    last: Instant,
+}
+
+/// Per-client brute-force protection for the login form. Attempts are counted when they
+/// start, not when they fail, so parallel guesses are held back as much as sequential ones.
+#[derive(Default)]
+pub struct LoginThrottle {
+    clients: std::sync::Mutex<HashMap<IpAddr, LoginAttempts>>,
+}
+
+impl LoginThrottle {
+    fn backoff(count: u32) -> std::time::Duration {
+        let doublings = count.saturating_sub(FREE_LOGIN_ATTEMPTS).min(20);
+        std::time::Duration::from_secs(1 << doublings).min(MAX_LOGIN_BACKOFF)
+    }
+
+    /// Counts an attempt from `client`, or returns how long it must wait before the next one.
+    pub fn begin(&self, client: IpAddr, now: Instant) -> std::result::Result<(), std::time::Duration> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if clients.len() > MAX_LOGIN_CLIENTS {
+            clients.retain(|_, a| now.duration_since(a.last) < LOGIN_ATTEMPTS_RESET);
+        }
+        let attempts = clients.entry(client).or_insert(LoginAttempts { count: 0, last: now });
+        if now.duration_since(attempts.last) >= LOGIN_ATTEMPTS_RESET {
+            attempts.count = 0;
+        }
+        if attempts.count >= FREE_LOGIN_ATTEMPTS {
+            let ready = attempts.last + Self::backoff(attempts.count);
+            if now < ready {
+                return Err(ready - now);
+            }
+        }
+        attempts.count += 1;
+        attempts.last = now;
+        Ok(())
+    }
+
+    /// Forgets a client's attempts after it logged in.
+    pub fn succeeded(&self, client: IpAddr) {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).remove(&client);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration as StdDuration;
+
+    #[test]
+    fn route_scopes() {
+        let cases = [
+            (Method::GET, "/api/v1/tokens", Scope::Admin),
+            (Method::POST, "/api/v1/tokens", Scope::Admin),
+            (Method::DELETE, "/api/v1/tokens/3f2a9c01b7de", Scope::Admin),
+            (Method::POST, "/blobs/gc", Scope::Admin),
+            (Method::GET, "/blobs/verify", Scope::Admin),
+            (Method::GET, "/audit", Scope::Admin),
+            (Method::GET, "/audit/verify", Scope::Admin),
+            (Method::GET, "/entries", Scope::Read),
+            (Method::HEAD, "/entries", Scope::Read),
+            (Method::GET, "/photos/views/1_neck.jpg", Scope::Read),
+            (Method::GET, "/metrics", Scope::Read),
+            (Method::POST, "/entry", Scope::Write),
+            (Method::PATCH, "/entry/1792370911190753579", Scope::Write),
+            (Method::DELETE, "/entry/1792370911190753579", Scope::Write),
+            (Method::POST, "/entry/1792370911190753579/restore", Scope::Write),
+        ];
+        for (method, path, scope) in cases {
+            assert_eq!(required_scope(&method, path), scope, "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn scopes_include_the_ones_before_them() {
+        assert!(Scope::Admin > Scope::Write && Scope::Write > Scope::Read);
+    }
+
+    #[test]
+    fn login_throttle_allows_free_attempts_then_backs_off() {
+        let throttle = LoginThrottle::default();
+        let client: IpAddr = "192.168.1.20".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..FREE_LOGIN_ATTEMPTS {
+            assert!(throttle.begin(client, now).is_ok());
+        }
+        assert_eq!(throttle.begin(client, now), Err(StdDuration::from_secs(1)));
+        assert!(throttle.begin(client, now + StdDuration::from_secs(1)).is_ok());
+        let later = now + StdDuration::from_secs(1);
+        assert_eq!(throttle.begin(client, later), Err(StdDuration::from_secs(2)));
+        // Other clients are unaffected
+        assert!(throttle.begin("192.168.1.21".parse().unwrap(), now).is_ok());
+    }
+
+    #[test]
+    fn login_backoff_is_capped() {
+        assert_eq!(LoginThrottle::backoff(FREE_LOGIN_ATTEMPTS), StdDuration::from_secs(1));
+        assert_eq!(LoginThrottle::backoff(FREE_LOGIN_ATTEMPTS + 3), StdDuration::from_secs(8));
+        assert_eq!(LoginThrottle::backoff(u32::MAX), MAX_LOGIN_BACKOFF);
+    }
+
+    #[test]
+    fn login_throttle_resets_on_success_and_after_a_quiet_hour() {
+        let throttle = LoginThrottle::default();
+        let client: IpAddr = "10.0.0.5".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..FREE_LOGIN_ATTEMPTS {
+            throttle.begin(client, now).unwrap();
+        }
+        assert!(throttle.begin(client, now).is_err());
+        assert!(throttle.begin(client, now + LOGIN_ATTEMPTS_RESET).is_ok());
+        throttle.succeeded(client);
+        for _ in 0..FREE_LOGIN_ATTEMPTS {
+            assert!(throttle.begin(client, now + LOGIN_ATTEMPTS_RESET).is_ok());
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::{anyhow, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::fs;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::crypto;
+use crate::paths::ProfileDirs;
+
+/// One stored blob. `refs` counts the photo names currently pointing at it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlobInfo {
+    ext: String,
+    size: u64,
+    refs: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BlobIndex {
+    /// sha256 hex -> blob
+    blobs: BTreeMap<String, BlobInfo>,
+    /// Photo name relative to /photos (e.g. "views/123_neck.jpg") -> sha256 hex
+    names: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    pub removed_blobs: usize,
+    pub removed_orphans: usize,
+    pub freed_bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub checked: usize,
+    pub ok: usize,
+    /// Blobs whose content no longer hashes to their name
+    pub corrupted: Vec<String>,
+    /// Blobs in the index with no file on disk
+    pub missing: Vec<String>,
+    /// Blobs whose reference count disagrees with the names pointing at them
+    pub refcount_mismatches: Vec<String>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty() && self.refcount_mismatches.is_empty()
+    }
+}
+
+/// The index as last read or written, with the file's modification time at that point.
+struct LoadedIndex {
+    index: BlobIndex,
+    modified: Option<SystemTime>,
+}
+
+/// The index locked against this process and every other one (the server and command-line
+/// subcommands share the store), freshly read from disk. The file lock is released on drop.
+struct WriteLock<'a> {
+    state: MutexGuard<'a, LoadedIndex>,
+    file: std::fs::File,
+}
+
+impl Drop for WriteLock<'_> {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Content-addressed photo storage: files are named by their SHA-256 so identical bytes are
+/// kept once, and public photo names map onto them through a reference-counted index.
+pub struct BlobStore {
+    dirs: ProfileDirs,
+    state: Mutex<LoadedIndex>,
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn name_from_url(url_path: &str) -> &str {
+    url_path.trim_start_matches("/photos/")
+}
+
+impl BlobStore {
+    /// Loads the index from a profile's blob directory, starting empty if there is none yet.
+    pub async fn open(dirs: ProfileDirs) -> Result<Self> {
+        fs::create_dir_all(dirs.blobs_dir()).await?;
+        let state = Mutex::new(Self::load(&dirs).await?);
+        Ok(BlobStore { dirs, state })
+    }
+
+    fn blob_path(&self, hash: &str, ext: &str) -> PathBuf {
+        self.dirs.blobs_dir().join(&hash[..2]).join(format!("{}.{}", hash, ext))
+    }
+
+    async fn index_modified(dirs: &ProfileDirs) -> Option<SystemTime> {
+        fs::metadata(dirs.blob_index_path()).await.ok()?.modified().ok()
+    }
+
+    async fn load(dirs: &ProfileDirs) -> Result<LoadedIndex> {
+        let modified = Self::index_modified(dirs).await;
+        let index = match fs::read_to_string(dirs.blob_index_path()).await {
+            Ok(j) => serde_json::from_str(&j).map_err(|e| anyhow!("corrupt blob index: {}", e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BlobIndex::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(LoadedIndex { index, modified })
+    }
+
+    /// Locks the index, first re-reading it if another process (such as the command line
+    /// running next to the server) has rewritten it since.
+    async fn lock(&self) -> MutexGuard<'_, LoadedIndex> {
+        let mut state = self.state.lock().await;
+        if Self::index_modified(&self.dirs).await != state.modified {
+            match Self::load(&self.dirs).await {
+                Ok(fresh) => *state = fresh,
+                Err(e) => tracing::warn!(error = %e, "could not reload blob index"),
+            }
+        }
+        state
+    }
+
+    /// Locks the index for a read-modify-write. The file lock is what keeps other processes
+    /// out; under it the index is always re-read, as a modification time can miss a rewrite.
+    async fn lock_for_write(&self) -> Result<WriteLock<'_>> {
+        let state = self.state.lock().await;
+        let path = self.dirs.blob_lock_path();
+        let file = tokio::task::spawn_blocking(move || -> std::io::Result<std::fs::File> {
+            let file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+            file.lock_exclusive()?;
+            Ok(file)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        let mut lock = WriteLock { state, file };
+        *lock.state = Self::load(&self.dirs).await?;
+        Ok(lock)
+    }
+
+    async fn persist(&self, state: &mut LoadedIndex) -> Result<()> {
+        let tmp = self.dirs.blob_index_path().with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&state.index)?).await?;
+        fs::rename(&tmp, self.dirs.blob_index_path()).await?;
+        state.modified = Self::index_modified(&self.dirs).await;
+        Ok(())
+    }
+
+    fn unref(index: &mut BlobIndex, name: &str) -> bool {
+        let Some(hash) = index.names.remove(name) else { return false };
+        if let Some(info) = index.blobs.get_mut(&hash) {
+            info.refs = info.refs.saturating_sub(1);
+        }
+        true
+    }
+
+    /// Re-reads the index from disk to confirm the store is usable; returns the blob count.
+    pub async fn check(&self) -> Result<usize> {
+        let loaded = Self::load(&self.dirs).await?;
+        if !fs::metadata(self.dirs.blobs_dir()).await?.is_dir() {
+            return Err(anyhow!("{} is not a directory", self.dirs.blobs_dir().display()));
+        }
+        Ok(loaded.index.blobs.len())
+    }
+
+    /// Stores `bytes` under the public photo URL (`/photos/...`), reusing an identical blob if one exists.
+    pub async fn put(&self, url_path: &str, bytes: &[u8]) -> Result<String> {
+        let name = name_from_url(url_path);
+        let ext = std::path::Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("bin").to_ascii_lowercase();
+        let hash = hash_bytes(bytes);
+
+        let mut lock = self.lock_for_write().await?;
+        let state = &mut *lock.state;
+        if !state.index.blobs.contains_key(&hash) {
+            let path = self.blob_path(&hash, &ext);
+            fs::create_dir_all(self.dirs.blobs_dir().join(&hash[..2])).await?;
+            let tmp = path.with_extension(format!("{}.tmp", ext));
+            fs::write(&tmp, crypto::seal(bytes.to_vec())?).await?;
+            fs::rename(&tmp, &path).await?;
+            state.index.blobs.insert(hash.clone(), BlobInfo { ext, size: bytes.len() as u64, refs: 0 });
+        }
+        Self::unref(&mut state.index, name);
+        state.index.names.insert(name.to_string(), hash.clone());
+        if let Some(info) = state.index.blobs.get_mut(&hash) {
+            info.refs += 1;
+        }
+        self.persist(state).await?;
+        Ok(hash)
+    }
+
+    /// Drops every photo name matching `pred`; the blobs stay until the next `gc`. Returns the
+    /// released names with the blob each pointed at.
+    pub async fn release_where(&self, pred: impl Fn(&str) -> bool) -> Result<BTreeMap<String, String>> {
+        let mut lock = self.lock_for_write().await?;
+        let state = &mut *lock.state;
+        let released: BTreeMap<String, String> = state.index.names.iter().filter(|(n, _)| pred(n)).map(|(n, h)| (n.clone(), h.clone())).collect();
+        for n in released.keys() {
+            Self::unref(&mut state.index, n);
+        }
+        if !released.is_empty() {
+            self.persist(state).await?;
+        }
+        Ok(released)
+    }
+
+    /// Points a released photo name at its blob again, if `gc` has not removed the blob yet.
+    pub async fn relink(&self, name: &str, hash: &str) -> Result<bool> {
+        let mut lock = self.lock_for_write().await?;
+        let state = &mut *lock.state;
+        let Some(info) = state.index.blobs.get(hash) else { return Ok(false) };
+        if !fs::try_exists(self.blob_path(hash, &info.ext)).await? {
+            return Ok(false);
+        }
+        Self::unref(&mut state.index, name);
+        state.index.names.insert(name.to_string(), hash.to_string());
+        if let Some(info) = state.index.blobs.get_mut(hash) {
+            info.refs += 1;
+        }
+        self.persist(state).await?;
+        Ok(true)
+    }
+
+    /// File on disk holding the photo at `url_path`; falls back to the legacy per-file layout.
+    pub async fn resolve(&self, url_path: &str) -> PathBuf {
+        let state = self.lock().await;
+        let index = &state.index;
+        match index.names.get(name_from_url(url_path)).and_then(|h| index.blobs.get(h).map(|b| (h, b))) {
+            Some((hash, info)) => self.blob_path(hash, &info.ext),
+            None => self.dirs.photo_file_path(url_path),
+        }
+    }
+
+    /// Deletes blobs nobody references any more, plus stray files the index does not know about.
+    pub async fn gc(&self) -> Result<GcReport> {
+        let mut lock = self.lock_for_write().await?;
+        let state = &mut *lock.state;
+        let mut report = GcReport::default();
+        let dead: Vec<String> = state.index.blobs.iter().filter(|(_, b)| b.refs == 0).map(|(h, _)| h.clone()).collect();
+        for hash in dead {
+            if let Some(info) = state.index.blobs.remove(&hash) {
+                match fs::remove_file(self.blob_path(&hash, &info.ext)).await {
+                    Ok(()) => report.freed_bytes += info.size,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+                report.removed_blobs += 1;
+            }
+        }
+        self.persist(state).await?;
+
+        let known: BTreeSet<PathBuf> = state.index.blobs.iter().map(|(h, b)| self.blob_path(h, &b.ext)).collect();
+        let mut shards = fs::read_dir(self.dirs.blobs_dir()).await?;
+        while let Some(shard) = shards.next_entry().await? {
+            if !shard.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut files = fs::read_dir(shard.path()).await?;
+            while let Some(f) = files.next_entry().await? {
+                let path = f.path();
+                if !known.contains(&path) {
+                    report.freed_bytes += f.metadata().await.map(|m| m.len()).unwrap_or(0);
+                    fs::remove_file(&path).await?;
+                    report.removed_orphans += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Re-hashes every blob and cross-checks the reference counts against the name table.
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        let state = self.lock().await;
+        let index = &state.index;
+        let mut report = VerifyReport::default();
+        let mut counted: BTreeMap<&str, u32> = BTreeMap::new();
+        for hash in index.names.values() {
+            *counted.entry(hash.as_str()).or_default() += 1;
+        }
+        for (hash, info) in &index.blobs {
+            report.checked += 1;
+            if counted.get(hash.as_str()).copied().unwrap_or(0) != info.refs {
+                report.refcount_mismatches.push(hash.clone());
+            }
+            // Blobs are named by the hash of the photo, so encrypted ones are checked decrypted
+            match fs::read(self.blob_path(hash, &info.ext)).await {
+                Ok(bytes) => match crypto::open(bytes) {
+                    Ok(plain) if hash_bytes(&plain) == *hash => report.ok += 1,
+                    _ => report.corrupted.push(hash.clone()),
+                },
+                Err(_) => report.missing.push(hash.clone()),
+            }
+        }
+        for hash in counted.keys() {
+            if !index.blobs.contains_key(*hash) {
+                report.missing.push(hash.to_string());
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn store() -> BlobStore {
+        let dir = std::env::temp_dir().join(format!("vital-tracker-blobs-{}", uuid::Uuid::new_v4()));
+        BlobStore::open(ProfileDirs::at(dir)).await.unwrap()
+    }
+
+    async fn refs(store: &BlobStore, hash: &str) -> Option<u32> {
+        store.lock().await.index.blobs.get(hash).map(|b| b.refs)
+    }
+
+    async fn blob_file(store: &BlobStore, hash: &str) -> PathBuf {
+        let ext = store.lock().await.index.blobs[hash].ext.clone();
+        store.blob_path(hash, &ext)
+    }
+
+    #[tokio::test]
+    async fn identical_bytes_are_stored_once() {
+        let store = store().await;
+        let a = store.put("/photos/1.jpg", b"same photo").await.unwrap();
+        let b = store.put("/photos/views/1_neck.jpg", b"same photo").await.unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a, hash_bytes(b"same photo"));
+        assert_eq!(refs(&store, &a).await, Some(2));
+        assert_eq!(store.check().await.unwrap(), 1);
+        assert_eq!(store.resolve("/photos/1.jpg").await, store.resolve("/photos/views/1_neck.jpg").await);
+        assert!(fs::try_exists(blob_file(&store, &a).await).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn refcounts_follow_overwrite_release_and_relink() {
+        let store = store().await;
+        let old = store.put("/photos/1.jpg", b"first").await.unwrap();
+        store.put("/photos/2.jpg", b"first").await.unwrap();
+        let new = store.put("/photos/1.jpg", b"second").await.unwrap();
+        assert_eq!(refs(&store, &old).await, Some(1));
+        assert_eq!(refs(&store, &new).await, Some(1));
+
+        // Writing the same bytes under the same name again does not double count
+        store.put("/photos/1.jpg", b"second").await.unwrap();
+        assert_eq!(refs(&store, &new).await, Some(1));
+
+        let released = store.release_where(|n| n == "2.jpg").await.unwrap();
+        assert_eq!(released.get("2.jpg"), Some(&old));
+        assert_eq!(refs(&store, &old).await, Some(0));
+        assert!(store.release_where(|n| n == "2.jpg").await.unwrap().is_empty());
+
+        assert!(store.relink("2.jpg", &old).await.unwrap());
+        assert_eq!(refs(&store, &old).await, Some(1));
+        assert!(!store.relink("3.jpg", &hash_bytes(b"never stored")).await.unwrap());
+        assert!(store.verify().await.unwrap().is_clean());
+    }
+
+    #[tokio::test]
+    async fn gc_removes_only_unreferenced_blobs() {
+        let store = store().await;
+        let kept = store.put("/photos/1.jpg", b"kept").await.unwrap();
+        let dropped = store.put("/photos/2.jpg", b"dropped").await.unwrap();
+        let dropped_file = blob_file(&store, &dropped).await;
+        store.release_where(|n| n == "2.jpg").await.unwrap();
+        let stray = store.dirs.blobs_dir().join("ab").join("stray.jpg");
+        fs::create_dir_all(stray.parent().unwrap()).await.unwrap();
+        fs::write(&stray, b"left over").await.unwrap();
+
+        let report = store.gc().await.unwrap();
+        assert_eq!((report.removed_blobs, report.removed_orphans), (1, 1));
+        assert_eq!(report.freed_bytes, (b"dropped".len() + b"left over".len()) as u64);
+        assert!(!fs::try_exists(&dropped_file).await.unwrap());
+        assert!(!fs::try_exists(&stray).await.unwrap());
+        assert!(fs::try_exists(blob_file(&store, &kept).await).await.unwrap());
+        assert!(fs::try_exists(store.dirs.blob_index_path()).await.unwrap());
+        assert_eq!(refs(&store, &dropped).await, None);
+        assert!(!store.relink("2.jpg", &dropped).await.unwrap());
+
+        let again = store.gc().await.unwrap();
+        assert_eq!((again.removed_blobs, again.removed_orphans), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn verify_reports_corrupted_and_missing_blobs() {
+        let store = store().await;
+        let good = store.put("/photos/1.jpg", b"good").await.unwrap();
+        let damaged = store.put("/photos/2.jpg", b"damaged").await.unwrap();
+        let gone = store.put("/photos/3.jpg", b"gone").await.unwrap();
+        assert!(store.verify().await.unwrap().is_clean());
+
+        fs::write(blob_file(&store, &damaged).await, b"bit rot").await.unwrap();
+        fs::remove_file(blob_file(&store, &gone).await).await.unwrap();
+        let report = store.verify().await.unwrap();
+        assert!(!report.is_clean());
+        assert_eq!((report.checked, report.ok), (3, 1));
+        assert_eq!(report.corrupted, vec![damaged]);
+        assert_eq!(report.missing, vec![gone]);
+        assert!(report.refcount_mismatches.is_empty());
+        assert_eq!(refs(&store, &good).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn changes_by_another_process_are_picked_up() {
+        let first = store().await;
+        let second = BlobStore::open(first.dirs.clone()).await.unwrap();
+        first.put("/photos/1.jpg", b"one").await.unwrap();
+        let two = second.put("/photos/2.jpg", b"two").await.unwrap();
+        // The second store re-read the index under the lock instead of dropping the first's name
+        let fresh = BlobStore::open(first.dirs.clone()).await.unwrap();
+        assert_eq!(fresh.check().await.unwrap(), 2);
+        assert_eq!(fresh.resolve("/photos/2.jpg").await, blob_file(&second, &two).await);
+        assert_eq!(first.gc().await.unwrap().removed_blobs, 0);
+        assert!(fresh.verify().await.unwrap().is_clean());
+    }
+}
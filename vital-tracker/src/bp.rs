@@ -0,0 +1,257 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// Published threshold set used to put a blood pressure reading in a category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Guideline {
+    /// 2017 ACC/AHA: normal, elevated, stage 1, stage 2, hypertensive crisis
+    Aha,
+    /// 2018 ESC/ESH office readings: normal (incl. optimal), high normal, grades 1-3
+    Esc,
+}
+
+impl Guideline {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "aha" | "acc/aha" => Some(Guideline::Aha),
+            "esc" | "esh" | "esc/esh" => Some(Guideline::Esc),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Guideline::Aha => "ACC/AHA 2017",
+            Guideline::Esc => "ESC/ESH 2018",
+        }
+    }
+
+    /// Lower bounds of every category above normal, lowest first.
+    pub fn bands(self) -> &'static [Band] {
+        match self {
+            Guideline::Aha => AHA_BANDS,
+            Guideline::Esc => ESC_BANDS,
+        }
+    }
+
+    /// The highest category whose systolic or diastolic bound the reading reaches. Works on
+    /// averages as well as single readings.
+    pub fn classify(self, sys: f64, dia: f64) -> Classification {
+        let band = self.bands().iter().rev().find(|b| b.reached_by(sys, dia));
+        match band {
+            Some(b) => Classification { category: b.category, label: b.label },
+            None => Classification { category: Category::Normal, label: "Normal" },
+        }
+    }
+}
+
+/// Categories shared by every guideline, so chart bands and the Influx `bp_class` field mean the same thing
+/// whichever set is selected; the labels carry each guideline's own wording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Normal,
+    Elevated,
+    #[serde(rename = "stage_1")]
+    Stage1,
+    #[serde(rename = "stage_2")]
+    Stage2,
+    Crisis,
+}
+
+impl Category {
+    /// Name written to the Influx `bp_class` field and shown on the command line, e.g. `stage_1`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Normal => "normal",
+            Category::Elevated => "elevated",
+            Category::Stage1 => "stage_1",
+            Category::Stage2 => "stage_2",
+            Category::Crisis => "crisis",
+        }
+    }
+}
+
+/// Where a category starts: a reading is in it when either pressure reaches its bound.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Band {
+    pub category: Category,
+    pub label: &'static str,
+    /// mmHg; `None` when diastolic pressure alone never puts a reading here
+    pub sys: Option<f64>,
+    pub dia: Option<f64>,
+    /// The bound itself belongs to the category below (AHA's crisis is "higher than 180/120")
+    pub exclusive: bool,
+}
+
+impl Band {
+    fn reached_by(&self, sys: f64, dia: f64) -> bool {
+        let reaches = |v: f64, bound: Option<f64>| bound.is_some_and(|b| if self.exclusive { v > b } else { v >= b });
+        reaches(sys, self.sys) || reaches(dia, self.dia)
+    }
+}
+
+const AHA_BANDS: &[Band] = &[
+    Band { category: Category::Elevated, label: "Elevated", sys: Some(120.0), dia: None, exclusive: false },
+    Band { category: Category::Stage1, label: "Stage 1 hypertension", sys: Some(130.0), dia: Some(80.0), exclusive: false },
+    Band { category: Category::Stage2, label: "Stage 2 hypertension", sys: Some(140.0), dia: Some(90.0), exclusive: false },
+    Band { category: Category::Crisis, label: "Hypertensive crisis", sys: Some(180.0), dia: Some(120.0), exclusive: true },
+];
+
+const ESC_BANDS: &[Band] = &[
+    Band { category: Category::Elevated, label: "High normal", sys: Some(130.0), dia: Some(85.0), exclusive: false },
+    Band { category: Category::Stage1, label: "Grade 1 hypertension", sys: Some(140.0), dia: Some(90.0), exclusive: false },
+    Band { category: Category::Stage2, label: "Grade 2 hypertension", sys: Some(160.0), dia: Some(100.0), exclusive: false },
+    Band { category: Category::Crisis, label: "Grade 3 hypertension", sys: Some(180.0), dia: Some(110.0), exclusive: false },
+];
+
+/// A reading's category and how the selected guideline names it.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Classification {
+    pub category: Category,
+    pub label: &'static str,
+}
+
+/// Hemodynamic values derived from a single reading.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Derived {
+    /// Mean arterial pressure, dia + (sys - dia) / 3, mmHg
+    pub map: f64,
+    /// sys - dia, mmHg
+    pub pulse_pressure: f64,
+    /// pulse / sys; `None` when no pulse was recorded
+    pub shock_index: Option<f64>,
+}
+
+impl Derived {
+    /// `None` when the pressures are missing (0), as in entries saved without readings.
+    pub fn from_reading(sys: f64, dia: f64, pulse: f64) -> Option<Self> {
+        if sys <= 0.0 || dia <= 0.0 {
+            return None;
+        }
+        Some(Derived {
+            map: dia + (sys - dia) / 3.0,
+            pulse_pressure: sys - dia,
+            shock_index: (pulse > 0.0).then(|| pulse / sys),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BpOptions {
+    /// Guideline used for entries, averages, chart bands and the Influx `bp_class` field
+    pub guideline: Guideline,
+}
+
+impl Default for BpOptions {
+    fn default() -> Self {
+        BpOptions { guideline: Guideline::Aha }
+    }
+}
+
+impl BpOptions {
+    /// Overrides the guideline from VITAL_BP_GUIDELINE (aha|esc).
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Ok(v) = env::var("VITAL_BP_GUIDELINE") {
+            self.guideline = Guideline::parse(&v).ok_or_else(|| anyhow!("unsupported VITAL_BP_GUIDELINE: {}", v))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(guideline: Guideline, cases: &[(f64, f64, Category)]) {
+        for &(sys, dia, expected) in cases {
+            assert_eq!(guideline.classify(sys, dia).category, expected, "{} {}/{}", guideline.name(), sys, dia);
+        }
+    }
+
+    #[test]
+    fn aha_boundaries() {
+        use Category::*;
+        check(
+            Guideline::Aha,
+            &[
+                (119.0, 79.0, Normal),
+                (120.0, 79.0, Elevated),
+                (129.0, 79.0, Elevated),
+                (120.0, 80.0, Stage1),
+                (130.0, 79.0, Stage1),
+                (130.0, 80.0, Stage1),
+                (139.0, 89.0, Stage1),
+                (140.0, 89.0, Stage2),
+                (139.0, 90.0, Stage2),
+                // Crisis is "higher than 180/120", so the bound itself is stage 2
+                (180.0, 120.0, Stage2),
+                (181.0, 119.0, Crisis),
+                (179.0, 121.0, Crisis),
+                (181.0, 121.0, Crisis),
+            ],
+        );
+    }
+
+    #[test]
+    fn esc_boundaries() {
+        use Category::*;
+        check(
+            Guideline::Esc,
+            &[
+                (129.0, 84.0, Normal),
+                (130.0, 84.0, Elevated),
+                (129.0, 85.0, Elevated),
+                (139.0, 89.0, Elevated),
+                (140.0, 89.0, Stage1),
+                (139.0, 90.0, Stage1),
+                (140.0, 90.0, Stage1),
+                (160.0, 99.0, Stage2),
+                (159.0, 100.0, Stage2),
+                (179.0, 109.0, Stage2),
+                (180.0, 109.0, Crisis),
+                (179.0, 110.0, Crisis),
+                (180.0, 110.0, Crisis),
+            ],
+        );
+    }
+
+    #[test]
+    fn isolated_systolic_is_classified_by_systolic_pressure() {
+        check(Guideline::Aha, &[(150.0, 70.0, Category::Stage2)]);
+        check(Guideline::Esc, &[(150.0, 70.0, Category::Stage1)]);
+    }
+
+    #[test]
+    fn labels_follow_the_guideline() {
+        assert_eq!(Guideline::Aha.classify(135.0, 85.0).label, "Stage 1 hypertension");
+        assert_eq!(Guideline::Esc.classify(135.0, 85.0).label, "High normal");
+        assert_eq!(Guideline::Esc.classify(110.0, 70.0).label, "Normal");
+    }
+
+    #[test]
+    fn derived_values() {
+        let d = Derived::from_reading(120.0, 80.0, 72.0).unwrap();
+        assert!((d.map - 93.333).abs() < 0.001);
+        assert_eq!(d.pulse_pressure, 40.0);
+        assert_eq!(d.shock_index, Some(0.6));
+        assert_eq!(Derived::from_reading(120.0, 80.0, 0.0).unwrap().shock_index, None);
+    }
+
+    #[test]
+    fn nothing_is_derived_without_pressures() {
+        assert!(Derived::from_reading(0.0, 0.0, 70.0).is_none());
+        assert!(Derived::from_reading(120.0, 0.0, 70.0).is_none());
+        assert!(Derived::from_reading(0.0, 80.0, 70.0).is_none());
+    }
+
+    #[test]
+    fn guideline_names_parse() {
+        assert_eq!(Guideline::parse(" ACC/AHA "), Some(Guideline::Aha));
+        assert_eq!(Guideline::parse("esh"), Some(Guideline::Esc));
+        assert_eq!(Guideline::parse("jnc7"), None);
+    }
+}
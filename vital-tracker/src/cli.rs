@@ -0,0 +1,239 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+use crate::auth::Scope;
+use crate::config::{Config, LogFormat};
+
+#[derive(Parser)]
+#[command(name = "vital-tracker", version, about = "Local vitals and symptom photo tracker")]
+pub struct Cli {
+    /// TOML configuration file (default: ./vital-tracker.toml if it exists)
+    #[arg(long, global = true, env = "VITAL_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, global = true)]
+    pub bind: Option<String>,
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// Directory holding entry metadata and photos
+    #[arg(long, global = true)]
+    pub data_root: Option<PathBuf>,
+    /// Serve the web UI from this directory instead of the built-in copy
+    #[arg(long, global = true)]
+    pub static_dir: Option<PathBuf>,
+    /// Do not write entries to InfluxDB
+    #[arg(long, global = true)]
+    pub no_influx: bool,
+    /// Log level or filter directives (e.g. debug, or info,vital_tracker=trace)
+    #[arg(long, global = true)]
+    pub log_level: Option<String>,
+    /// Log as JSON lines instead of text
+    #[arg(long, global = true)]
+    pub log_json: bool,
+    /// Serve over HTTPS (self-signed unless a certificate is configured)
+    #[arg(long, global = true)]
+    pub tls: bool,
+    /// Serve without requiring a login (trusted machines only)
+    #[arg(long, global = true)]
+    pub no_auth: bool,
+    /// Profile (person) whose entries a command works on; the first configured one by default
+    #[arg(long, global = true, env = "VITAL_PROFILE")]
+    pub profile: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Record a reading, optionally with photos
+    Add(AddArgs),
+    /// Print stored entries as a table
+    List(ListArgs),
+    /// Write entries (and optionally their photos) out for backup or analysis
+    Export(ExportArgs),
+    /// Load entries from a JSON export
+    Import(ImportArgs),
+    /// Check stored photos and entry metadata for damage
+    Verify,
+    /// Encrypt all stored entries and photos in place
+    Encrypt,
+    /// Decrypt all stored entries and photos in place
+    Decrypt,
+    /// Show or check the audit log of data changes
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+    /// Set the password for the web UI
+    Passwd(PasswdArgs),
+    /// Manage API tokens for scripts
+    Token {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Args)]
+pub struct AddArgs {
+    /// Systolic pressure, mmHg
+    #[arg(long)]
+    pub sys: i64,
+    /// Diastolic pressure, mmHg
+    #[arg(long)]
+    pub dia: i64,
+    #[arg(long)]
+    pub pulse: i64,
+    /// Body temperature, °C
+    #[arg(long)]
+    pub temp: f64,
+    #[arg(long)]
+    pub temp_jaw: Option<f64>,
+    #[arg(long)]
+    pub temp_room: Option<f64>,
+    /// Pain score, 0-10
+    #[arg(long)]
+    pub pain: Option<i64>,
+    #[arg(long, value_name = "FILE")]
+    pub front: Option<PathBuf>,
+    #[arg(long, value_name = "FILE")]
+    pub left: Option<PathBuf>,
+    #[arg(long, value_name = "FILE")]
+    pub right: Option<PathBuf>,
+    #[arg(long, value_name = "FILE")]
+    pub neck: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    /// Only entries newer than this: a duration such as 7d, 12h or 2w, or a date (YYYY-MM-DD)
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Print JSON instead of a table
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value = "json")]
+    pub format: ExportFormat,
+    /// File to write; standard output when omitted
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+    /// Also copy every entry's photos (decrypted) into this directory, laid out like /photos
+    #[arg(long, value_name = "DIR")]
+    pub photos_dir: Option<PathBuf>,
+    /// Only entries newer than this (same forms as `list --since`)
+    #[arg(long)]
+    pub since: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// JSON file written by `export` (or saved from /entries)
+    pub file: PathBuf,
+    /// Directory holding the photos written by `export --photos-dir`
+    #[arg(long, value_name = "DIR")]
+    pub photos_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct PasswdArgs {
+    /// Read the password from the first line of standard input instead of prompting
+    #[arg(long)]
+    pub stdin: bool,
+}
+
+#[derive(Subcommand)]
+pub enum TokenAction {
+    /// Create a token and print its secret (shown only once)
+    Create {
+        /// What the token is for, e.g. "cron" or "phone"
+        name: String,
+        #[arg(long, value_enum, default_value = "read")]
+        scope: Scope,
+        /// Lifetime such as 30d, 12h or 8w; tokens without one never expire
+        #[arg(long, value_name = "DURATION")]
+        expires: Option<String>,
+    },
+    /// Show all tokens with their scope, expiry and last use
+    List {
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete a token so it stops working immediately
+    Revoke {
+        /// Token id as shown by `token list`
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    /// Print the most recent audit records, newest first
+    List {
+        /// Only records about this entry id
+        #[arg(long)]
+        entry: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        #[arg(long)]
+        json: bool,
+    },
+    /// Recompute the hash chain and report the first record that was altered or removed
+    Verify,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print the effective configuration and report whether it is valid
+    Check,
+}
+
+impl Cli {
+    /// Loads the configuration file and environment, then applies the command-line flags on top.
+    pub fn load_config(&self) -> anyhow::Result<Config> {
+        let mut config = Config::load(self.config.as_deref())?;
+        if let Some(bind) = &self.bind {
+            config.server.bind = bind.clone();
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+        if let Some(root) = &self.data_root {
+            config.paths.data_root = root.clone();
+        }
+        if let Some(dir) = &self.static_dir {
+            config.paths.static_dir = Some(dir.clone());
+        }
+        if self.no_influx {
+            config.features.influx = false;
+        }
+        if let Some(level) = &self.log_level {
+            config.logging.level = level.clone();
+        }
+        if self.log_json {
+            config.logging.format = LogFormat::Json;
+        }
+        if self.tls {
+            config.tls.enabled = true;
+        }
+        if self.no_auth {
+            config.auth.enabled = false;
+        }
+        Ok(config)
+    }
+}
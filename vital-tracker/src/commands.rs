@@ -0,0 +1,483 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, Utc};
+use std::fmt::Write as _;
+use tokio::fs;
+
+use crate::audit::{self, Action, Event};
+use crate::auth::{AuthStore, Scope};
+use crate::bp::Guideline;
+use crate::cli::{AddArgs, ExportArgs, ExportFormat, ImportArgs, ListArgs, PasswdArgs};
+use crate::config::{Config, ProfileConfig};
+use crate::crypto;
+use crate::db::influx::InfluxClient;
+use crate::entries::{self, AddError, Entry, EntryStore, NewEntry};
+use crate::paths::{self, ProfileDirs};
+
+/// A span such as 30m, 12h, 7d or 2w; a bare number means days.
+fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: i64 = num.parse().map_err(|_| anyhow!("invalid duration '{}', expected e.g. 7d or 12h", s))?;
+    let span = match unit {
+        "m" => Duration::try_minutes(n),
+        "h" => Duration::try_hours(n),
+        "d" | "" => Duration::try_days(n),
+        "w" => Duration::try_weeks(n),
+        _ => return Err(anyhow!("invalid duration unit '{}', expected m, h, d or w", unit)),
+    };
+    span.ok_or_else(|| anyhow!("duration '{}' is too long", s))
+}
+
+/// Earliest timestamp (nanoseconds) selected by `--since`: a duration back from now such as
+/// 30m, 12h, 7d or 2w, or a local date.
+fn parse_since(s: &str) -> Result<i128> {
+    let s = s.trim();
+    if let Ok(day) = entries::parse_day(s) {
+        return Ok(entries::local_day_start_nanos(day));
+    }
+    let span = parse_duration(s).map_err(|e| anyhow!("invalid --since: {}; a date (YYYY-MM-DD) also works", e))?;
+    let cutoff = Utc::now().checked_sub_signed(span).ok_or_else(|| anyhow!("invalid --since: '{}' reaches too far back", s))?;
+    Ok(cutoff.timestamp() as i128 * 1_000_000_000 + cutoff.timestamp_subsec_nanos() as i128)
+}
+
+/// A profile's entries, oldest first, optionally limited by `--since`.
+async fn selected_entries(profile: &str, since: Option<&str>) -> Result<Vec<Entry>> {
+    let from = since.map(parse_since).transpose()?.unwrap_or(i128::MIN);
+    let mut list: Vec<Entry> = entries::read_all_entries(&ProfileDirs::new(profile)).await.into_iter().filter(|e| e.timestamp_nanos >= from).collect();
+    list.sort_by_key(|e| e.timestamp_nanos);
+    Ok(list)
+}
+
+async fn read_photo(path: Option<&std::path::Path>) -> Result<Option<Vec<u8>>> {
+    match path {
+        Some(p) => Ok(Some(fs::read(p).await.with_context(|| format!("reading {}", p.display()))?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn add(config: &Config, profile: &str, args: AddArgs) -> Result<()> {
+    let store = EntryStore::open(config, profile).await?;
+    let photos = [
+        read_photo(args.front.as_deref()).await?,
+        read_photo(args.left.as_deref()).await?,
+        read_photo(args.right.as_deref()).await?,
+        read_photo(args.neck.as_deref()).await?,
+    ];
+    let input = NewEntry {
+        sys: Some(args.sys),
+        dia: Some(args.dia),
+        pulse: Some(args.pulse),
+        temp: Some(args.temp),
+        temp_jaw: args.temp_jaw,
+        temp_room: args.temp_room,
+        pain: args.pain,
+        photos,
+    };
+    let added = store.add(input, &audit::cli_actor()).await.map_err(|e| match e {
+        AddError::Failed(e) => e,
+        other => anyhow!("{}", other),
+    })?;
+    for w in &added.warnings {
+        eprintln!("warning: {}", w);
+    }
+    if config.features.influx {
+        if let Err(e) = entries::send_to_influx(&InfluxClient::new(&config.influx), &store, &added.entry).await {
+            eprintln!("warning: entry saved but not written to Influx: {:#}", e);
+        }
+    }
+    println!("Added entry {} ({})", added.entry.timestamp_nanos, entries::local_time_label(added.entry.timestamp_nanos));
+    Ok(())
+}
+
+fn opt<T: std::fmt::Display>(v: Option<T>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+pub async fn list(config: &Config, profile: &str, args: ListArgs) -> Result<()> {
+    let guideline = config.blood_pressure.guideline;
+    let list = selected_entries(profile, args.since.as_deref()).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&annotated(&list, guideline))?);
+        return Ok(());
+    }
+    println!(
+        "{:<19} {:<21} {:>7} {:<9} {:>5} {:>6} {:>5} {:>6} {:>6} {:>5} {:>6}",
+        "ID", "TIME", "BP", "CLASS", "MAP", "PULSE", "SI", "TEMP", "JAW", "PAIN", "PHOTOS"
+    );
+    for e in &list {
+        let derived = e.derived();
+        println!(
+            "{:<19} {:<21} {:>7} {:<9} {:>5} {:>6} {:>5} {:>6.1} {:>6} {:>5} {:>6}",
+            e.timestamp_nanos,
+            entries::local_time_label(e.timestamp_nanos),
+            format!("{}/{}", e.sys, e.dia),
+            e.classify(guideline).map_or("-", |c| c.category.as_str()),
+            opt(derived.map(|d| format!("{:.0}", d.map))),
+            e.pulse,
+            opt(derived.and_then(|d| d.shock_index).map(|si| format!("{:.2}", si))),
+            e.temp_c,
+            opt(e.temp_jaw.map(|t| format!("{:.1}", t))),
+            opt(e.pain),
+            e.views.len().max(usize::from(!e.path.is_empty())),
+        );
+    }
+    eprintln!("{} entries", list.len());
+    // Entries saved without readings would drag the average down
+    let measured: Vec<&Entry> = list.iter().filter(|e| e.has_bp()).collect();
+    if !measured.is_empty() {
+        let n = measured.len() as f64;
+        let (sys, dia) = (measured.iter().map(|e| e.sys as f64).sum::<f64>() / n, measured.iter().map(|e| e.dia as f64).sum::<f64>() / n);
+        eprintln!("average {:.0}/{:.0}: {} ({})", sys, dia, guideline.classify(sys, dia).label, guideline.name());
+    }
+    Ok(())
+}
+
+fn annotated(list: &[Entry], guideline: Guideline) -> Vec<serde_json::Value> {
+    list.iter().map(|e| e.to_annotated_json(guideline)).collect()
+}
+
+fn to_csv(list: &[Entry], guideline: Guideline) -> String {
+    let mut out = String::from("timestamp_nanos,time,sys,dia,bp_class,pulse,map,pulse_pressure,shock_index,temp_c,temp_jaw,temp_room,pain,photo\n");
+    for e in list {
+        let cell = |v: Option<String>| v.unwrap_or_default();
+        let derived = e.derived();
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            e.timestamp_nanos,
+            entries::local_time_label(e.timestamp_nanos),
+            e.sys,
+            e.dia,
+            e.classify(guideline).map_or("", |c| c.category.as_str()),
+            e.pulse,
+            cell(derived.map(|d| format!("{:.1}", d.map))),
+            cell(derived.map(|d| d.pulse_pressure.to_string())),
+            cell(derived.and_then(|d| d.shock_index).map(|si| format!("{:.3}", si))),
+            e.temp_c,
+            cell(e.temp_jaw.map(|t| t.to_string())),
+            cell(e.temp_room.map(|t| t.to_string())),
+            cell(e.pain.map(|p| p.to_string())),
+            e.path
+        );
+    }
+    out
+}
+
+pub async fn export(config: &Config, profile: &str, args: ExportArgs) -> Result<()> {
+    let list = selected_entries(profile, args.since.as_deref()).await?;
+    let body = match args.format {
+        ExportFormat::Json => serde_json::to_string_pretty(&annotated(&list, config.blood_pressure.guideline))? + "\n",
+        ExportFormat::Csv => to_csv(&list, config.blood_pressure.guideline),
+    };
+    if let Some(dir) = &args.photos_dir {
+        let store = EntryStore::open(config, profile).await?;
+        let mut copied = 0;
+        for e in &list {
+            for url in e.photo_urls() {
+                let name = url.trim_start_matches("/photos/");
+                if !paths::is_contained(name) {
+                    eprintln!("warning: not copying {}: unsafe photo path", url);
+                    continue;
+                }
+                let src = store.blobs.resolve(url).await;
+                let dest = dir.join(name);
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                // Exports are plaintext even when the archive is encrypted
+                let copy = async { fs::write(&dest, crypto::read(&src).await?).await.map_err(anyhow::Error::from) };
+                match copy.await {
+                    Ok(()) => copied += 1,
+                    Err(e) => eprintln!("warning: could not copy {}: {:#}", url, e),
+                }
+            }
+        }
+        eprintln!("Copied {} photos to {}", copied, dir.display());
+    }
+    match &args.output {
+        Some(path) => {
+            fs::write(path, body).await.with_context(|| format!("writing {}", path.display()))?;
+            eprintln!("Exported {} entries to {}", list.len(), path.display());
+        }
+        None => print!("{}", body),
+    }
+    Ok(())
+}
+
+pub async fn import(config: &Config, profile: &str, args: ImportArgs) -> Result<()> {
+    let text = fs::read_to_string(&args.file).await.with_context(|| format!("reading {}", args.file.display()))?;
+    let list: Vec<Entry> = serde_json::from_str(&text).with_context(|| format!("parsing {}", args.file.display()))?;
+    let store = EntryStore::open(config, profile).await?;
+    let actor = audit::cli_actor();
+    let (mut imported, mut existing, mut failed) = (0, 0, 0);
+    for e in &list {
+        match store.import(e, args.photos_dir.as_deref(), &actor).await {
+            Ok(true) => imported += 1,
+            Ok(false) => existing += 1,
+            Err(err) => {
+                failed += 1;
+                eprintln!("skipped entry {}: {}", e.timestamp_nanos, err);
+            }
+        }
+    }
+    println!("Imported {} entries, {} already present, {} skipped", imported, existing, failed);
+    if failed > 0 {
+        return Err(anyhow!("{} entries could not be imported", failed));
+    }
+    Ok(())
+}
+
+/// Re-hashes every blob and checks each entry's metadata and photos, for the given profiles,
+/// then the audit chain. Errors when anything is wrong.
+pub async fn verify(config: &Config, profiles: &[&ProfileConfig]) -> Result<()> {
+    let mut problems = 0;
+    for p in profiles {
+        if profiles.len() > 1 {
+            println!("Profile {}:", p.id);
+        }
+        problems += verify_profile(config, &p.id).await?;
+    }
+
+    let chain = audit::verify().await?;
+    match &chain.problem {
+        Some(p) => {
+            println!("Audit log: {} records intact, then {}", chain.records, p);
+            problems += 1;
+        }
+        None => println!("Audit log: {} records, chain intact", chain.records),
+    }
+    if problems > 0 {
+        return Err(anyhow!("{} problems found", problems));
+    }
+    println!("No problems found");
+    Ok(())
+}
+
+/// Checks one profile's blobs and entries, printing what is wrong; returns the problem count.
+async fn verify_profile(config: &Config, profile: &str) -> Result<usize> {
+    let store = EntryStore::open(config, profile).await?;
+    let report = store.blobs.verify().await?;
+    println!("Blobs: {} checked, {} ok", report.checked, report.ok);
+    for (what, hashes) in [("corrupted", &report.corrupted), ("missing", &report.missing), ("refcount mismatch", &report.refcount_mismatches)] {
+        for h in hashes {
+            println!("  {}: {}", what, h);
+        }
+    }
+    let mut problems = report.corrupted.len() + report.missing.len() + report.refcount_mismatches.len();
+
+    let mut files = fs::read_dir(store.dirs.json_dir()).await?;
+    while let Some(f) = files.next_entry().await? {
+        let path = f.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let parsed = crypto::read(&path).await.and_then(|j| serde_json::from_slice::<Entry>(&j).map_err(anyhow::Error::from));
+        if let Err(e) = parsed {
+            println!("  unreadable metadata {}: {}", path.display(), e);
+            problems += 1;
+        }
+    }
+    let list = entries::read_all_entries(&store.dirs).await;
+    for e in &list {
+        for url in e.photo_urls() {
+            if !fs::try_exists(store.blobs.resolve(url).await).await.unwrap_or(false) {
+                println!("  entry {}: photo {} is missing", e.timestamp_nanos, url);
+                problems += 1;
+            }
+        }
+    }
+    println!("Entries: {} checked", list.len());
+    Ok(problems)
+}
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+pub async fn passwd(config: &Config, args: PasswdArgs) -> Result<()> {
+    let password = if args.stdin {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    } else {
+        let first = rpassword::prompt_password("New password: ")?;
+        if rpassword::prompt_password("Repeat password: ")? != first {
+            return Err(anyhow!("passwords do not match"));
+        }
+        first
+    };
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(anyhow!("password must be at least {} characters", MIN_PASSWORD_LEN));
+    }
+    fs::create_dir_all(paths::data_root()).await?;
+    AuthStore::open(config.auth.session_ttl_hours).await?.set_password(&password).await?;
+    println!("Password set; existing logins were signed out");
+    Ok(())
+}
+
+pub async fn token_create(config: &Config, name: &str, scope: Scope, expires: Option<&str>) -> Result<()> {
+    let valid_for = expires.map(parse_duration).transpose()?;
+    fs::create_dir_all(paths::data_root()).await?;
+    let (token, secret) = AuthStore::open(config.auth.session_ttl_hours).await?.create_token(name, scope, valid_for).await?;
+    audit::record(&audit::cli_actor(), Event::new(Action::TokenCreate).after(&token)).await;
+    eprintln!("Created {} token {} ({}). Store the secret now; it cannot be shown again:", token.scope.as_str(), token.id, token.name);
+    println!("{}", secret);
+    Ok(())
+}
+
+pub async fn token_list(config: &Config, json: bool) -> Result<()> {
+    let tokens = AuthStore::open(config.auth.session_ttl_hours).await?.list_tokens().await;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&tokens)?);
+        return Ok(());
+    }
+    let now = Utc::now();
+    let when = |t: Option<chrono::DateTime<Utc>>| t.map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string()).unwrap_or_else(|| "-".to_string());
+    println!("{:<12} {:<20} {:<6} {:<16} {:<16} {:<16}", "ID", "NAME", "SCOPE", "CREATED", "EXPIRES", "LAST USED");
+    for t in &tokens {
+        let expires = if t.is_expired(now) { "expired".to_string() } else { when(t.expires) };
+        println!("{:<12} {:<20} {:<6} {:<16} {:<16} {:<16}", t.id, t.name, t.scope.as_str(), when(Some(t.created)), expires, when(t.last_used));
+    }
+    eprintln!("{} tokens", tokens.len());
+    Ok(())
+}
+
+pub async fn token_revoke(config: &Config, id: &str) -> Result<()> {
+    if !AuthStore::open(config.auth.session_ttl_hours).await?.revoke_token(id).await? {
+        return Err(anyhow!("no token with id {}", id));
+    }
+    audit::record(&audit::cli_actor(), Event::new(Action::TokenRevoke).detail(format!("token {}", id))).await;
+    println!("Revoked token {}", id);
+    Ok(())
+}
+
+/// Files `encrypt` and `decrypt` convert: entry metadata, blobs and legacy photo files of
+/// every profile, including ones no longer configured.
+async fn stored_files() -> Result<Vec<std::path::PathBuf>> {
+    let default = ProfileDirs::new(paths::DEFAULT_PROFILE);
+    let mut out = Vec::new();
+    let mut pending = vec![default.json_dir(), default.blobs_dir(), default.photos_dir(), paths::profiles_dir()];
+    while let Some(dir) = pending.pop() {
+        let mut files = match fs::read_dir(&dir).await {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(f) = files.next_entry().await? {
+            let path = f.path();
+            if f.file_type().await?.is_dir() {
+                pending.push(path);
+            } else if !is_blob_bookkeeping(&path) && path.extension().and_then(|e| e.to_str()) != Some("tmp") {
+                out.push(path);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// The blob index and its lock file, which stay plaintext.
+fn is_blob_bookkeeping(path: &std::path::Path) -> bool {
+    path.file_name().is_some_and(|n| n == "index.json" || n == "index.lock") && path.parent().and_then(|p| p.file_name()).is_some_and(|n| n == "blobs")
+}
+
+/// Rewrites every stored file encrypted (`encrypt = true`) or as plaintext, skipping files
+/// already in the wanted form. Each file is replaced atomically, so an interrupted run can
+/// simply be repeated.
+pub async fn convert(config: &Config, encrypt: bool) -> Result<()> {
+    let (mut converted, mut skipped, mut failed) = (0, 0, 0);
+    for path in stored_files().await? {
+        let result = async {
+            let bytes = fs::read(&path).await?;
+            if crypto::is_encrypted(&bytes) == encrypt {
+                return Ok(false);
+            }
+            let out = if encrypt { crypto::encrypt(&bytes)? } else { crypto::open(bytes)? };
+            let tmp = path.with_extension("convert.tmp");
+            fs::write(&tmp, out).await?;
+            fs::rename(&tmp, &path).await?;
+            anyhow::Ok(true)
+        };
+        match result.await {
+            Ok(true) => converted += 1,
+            Ok(false) => skipped += 1,
+            Err(e) => {
+                failed += 1;
+                eprintln!("could not convert {}: {:#}", path.display(), e);
+            }
+        }
+    }
+    let verb = if encrypt { "Encrypted" } else { "Decrypted" };
+    println!("{} {} files, {} already done", verb, converted, skipped);
+    if let Err(e) = audit::reencode().await {
+        failed += 1;
+        eprintln!("could not convert the audit log: {:#}", e);
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} files could not be converted; fix the problem and run the command again", failed));
+    }
+    if encrypt && !config.encryption.enabled {
+        eprintln!("note: set encryption.enabled = true (or VITAL_ENCRYPT=1) so new entries are encrypted too");
+    }
+    if !encrypt {
+        crypto::remove_params().await?;
+        if config.encryption.enabled {
+            eprintln!("note: encryption.enabled is still on; new entries will be encrypted again with a new key");
+        }
+    }
+    Ok(())
+}
+
+pub async fn audit_list(profile: Option<&str>, entry: Option<&str>, limit: usize, json: bool) -> Result<()> {
+    let records: Vec<audit::Record> = audit::read_all()
+        .await?
+        .into_iter()
+        .rev()
+        .filter(|r| profile.is_none_or(|p| r.entry_id.is_some() && r.is_for_profile(p)))
+        .filter(|r| entry.is_none() || r.entry_id.as_deref() == entry)
+        .take(limit)
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+    println!("{:>6} {:<19} {:<24} {:<12} {:<19}", "SEQ", "TIME", "ACTOR", "ACTION", "ENTRY");
+    for r in &records {
+        let action = serde_json::to_value(r.action)?.as_str().unwrap_or_default().to_string();
+        let time = r.time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S");
+        println!("{:>6} {:<19} {:<24} {:<12} {:<19}", r.seq, time, r.actor, action, r.entry_id.as_deref().unwrap_or("-"));
+    }
+    eprintln!("{} records", records.len());
+    Ok(())
+}
+
+pub async fn audit_verify() -> Result<()> {
+    let report = audit::verify().await?;
+    match report.problem {
+        Some(p) => Err(anyhow!("audit log broken after {} intact records: {}", report.records, p)),
+        None => {
+            println!("{} records, chain intact; head {}", report.records, report.head);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30m").unwrap(), Duration::minutes(30));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("7").unwrap(), Duration::days(7));
+        assert_eq!(parse_duration(" 2w ").unwrap(), Duration::weeks(2));
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+
+    #[test]
+    fn huge_durations_are_errors() {
+        assert!(parse_duration("999999999999d").is_err());
+        assert!(parse_duration("99999999999999999999d").is_err());
+        assert!(parse_since("99999999999d").is_err());
+        assert!(parse_since("2000000000d").is_err());
+    }
+}
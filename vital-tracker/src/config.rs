@@ -0,0 +1,643 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use crate::bp::BpOptions;
+use crate::imaging::calibration::CalibrationOptions;
+use crate::imaging::color::ColorOptions;
+use crate::imaging::output::ImageOptions;
+use crate::imaging::quality::QualityOptions;
+use crate::paths;
+
+/// Read from the working directory when no `--config` / VITAL_CONFIG is given.
+pub const DEFAULT_CONFIG_FILE: &str = "vital-tracker.toml";
+
+/// `Some(true)` for 1/true/on/yes, `Some(false)` for anything else, `None` when unset.
+pub fn env_flag(key: &str) -> Option<bool> {
+    env::var(key).ok().map(|v| {
+        let v = v.trim();
+        v == "1" || v.eq_ignore_ascii_case("true") || v.eq_ignore_ascii_case("on") || v.eq_ignore_ascii_case("yes")
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Address to listen on; 127.0.0.1 keeps the app local to this machine
+    pub bind: String,
+    pub port: u16,
+    /// How long a stop signal waits for in-flight uploads and queued Influx writes
+    pub shutdown_timeout_secs: u64,
+    /// New photos are refused, and `/health/ready` fails, when the data root's disk has less
+    /// free space than this
+    pub min_free_disk_mb: u64,
+    /// Other origins (e.g. `https://tracker.example.org` behind a proxy that rewrites Host)
+    /// allowed to send changing requests; the server's own origin always is
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { bind: "127.0.0.1".to_string(), port: 8081, shutdown_timeout_secs: 30, min_free_disk_mb: 200, allowed_origins: Vec::new() }
+    }
+}
+
+impl ServerConfig {
+    pub fn addr(&self) -> Result<SocketAddr> {
+        let ip: IpAddr = self.bind.trim().parse().map_err(|_| anyhow!("server.bind must be an IP address, got '{}'", self.bind))?;
+        Ok(SocketAddr::new(ip, self.port))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    /// Directory holding entry metadata, photos and blobs; relative paths in the config
+    /// file are taken relative to the file
+    pub data_root: PathBuf,
+    /// Serve the web UI from this directory instead of the copy built into the binary
+    pub static_dir: Option<PathBuf>,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig { data_root: paths::default_data_root(), static_dir: None }
+    }
+}
+
+/// A person whose entries are kept apart from everyone else's.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    /// Short name used in URLs, directory names and Influx tags
+    pub id: String,
+    /// Shown in the UI; defaults to the id
+    #[serde(default)]
+    pub name: String,
+}
+
+impl ProfileConfig {
+    pub fn display_name(&self) -> &str {
+        if self.name.is_empty() {
+            &self.id
+        } else {
+            &self.name
+        }
+    }
+}
+
+fn default_profiles() -> Vec<ProfileConfig> {
+    vec![ProfileConfig { id: paths::DEFAULT_PROFILE.to_string(), name: "Default".to_string() }]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InfluxConfig {
+    pub url: String,
+    pub token: Option<String>,
+    /// Set for InfluxDB 2.x; without it the 1.x compatibility API is used
+    pub org: Option<String>,
+    pub bucket: String,
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        InfluxConfig { url: "http://localhost:8086".to_string(), token: None, org: None, bucket: "default".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Level or filter directives, e.g. "info" or "info,vital_tracker=debug"
+    pub level: String,
+    /// Format of both the console and the log file
+    pub format: LogFormat,
+    /// Also write logs under <data_root>/logs
+    pub file: bool,
+    pub rotation: LogRotation,
+    /// Rotated files kept before the oldest is deleted
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: "info".to_string(), format: LogFormat::Text, file: true, rotation: LogRotation::Daily, max_files: 14 }
+    }
+}
+
+/// HTTPS for LAN use: browsers only allow camera access on localhost or over HTTPS.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /// PEM certificate chain and private key; a self-signed pair is generated when omitted
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    /// Extra host names or IP addresses the self-signed certificate should cover, e.g.
+    /// the machine's LAN address when binding to 0.0.0.0
+    pub hostnames: Vec<String>,
+}
+
+/// Encryption of entry metadata and photos on disk. The passphrase comes from
+/// `VITAL_PASSPHRASE` or a prompt and is never read from this file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    /// Use the contents of this file as the secret instead of a passphrase
+    pub key_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Require a login or API token for everything except the health checks. Only turn
+    /// this off on a machine nobody else can reach.
+    pub enabled: bool,
+    /// How long a UI login lasts
+    pub session_ttl_hours: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig { enabled: true, session_ttl_hours: 24 * 30 }
+    }
+}
+
+/// Protection against runaway clients, such as a stuck script posting entries in a loop.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Largest request body accepted; an entry with four phone photos is typically 10-20 MB
+    pub max_body_mb: u64,
+    /// Changing requests (POST, PATCH, DELETE) one client address may make per minute; 0 for no limit
+    pub client_writes_per_minute: u32,
+    /// Authenticated changing requests all clients together may make per minute; 0 for no limit
+    pub global_writes_per_minute: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig { max_body_mb: 64, client_writes_per_minute: 30, global_writes_per_minute: 120 }
+    }
+}
+
+/// Optional parts of the app that can be switched off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Features {
+    /// Mirror every entry to InfluxDB
+    pub influx: bool,
+    /// Measure Lab colour and redness of each view's region of interest
+    pub color_metrics: bool,
+    pub timelapse: bool,
+    pub compare: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features { influx: true, color_metrics: true, timelapse: true, compare: true }
+    }
+}
+
+/// Effective configuration: defaults, then the TOML file, then environment variables, then CLI flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub paths: PathsConfig,
+    /// People tracked by this instance; the first is used when a request names none
+    pub profiles: Vec<ProfileConfig>,
+    pub features: Features,
+    pub logging: LoggingConfig,
+    pub auth: AuthConfig,
+    pub encryption: EncryptionConfig,
+    pub limits: LimitsConfig,
+    pub influx: InfluxConfig,
+    pub blood_pressure: BpOptions,
+    pub image: ImageOptions,
+    pub quality: QualityOptions,
+    pub color: ColorOptions,
+    pub calibration: CalibrationOptions,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            server: Default::default(),
+            tls: Default::default(),
+            paths: Default::default(),
+            profiles: default_profiles(),
+            features: Default::default(),
+            logging: Default::default(),
+            auth: Default::default(),
+            encryption: Default::default(),
+            limits: Default::default(),
+            influx: Default::default(),
+            blood_pressure: Default::default(),
+            image: Default::default(),
+            quality: Default::default(),
+            color: Default::default(),
+            calibration: Default::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path`, or `vital-tracker.toml` in the working directory if present, and applies
+    /// environment overrides on top. An explicitly named file has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut config = match path {
+            Some(p) => Self::from_file(p)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading config file {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text).with_context(|| format!("parsing config file {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        config.paths.data_root = base.join(&config.paths.data_root);
+        config.paths.static_dir = config.paths.static_dir.map(|d| base.join(d));
+        config.tls.cert = config.tls.cert.map(|p| base.join(p));
+        config.tls.key = config.tls.key.map(|p| base.join(p));
+        config.encryption.key_file = config.encryption.key_file.map(|p| base.join(p));
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(v) = env::var("VITAL_BIND") {
+            self.server.bind = v;
+        }
+        if let Ok(v) = env::var("VITAL_PORT") {
+            self.server.port = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_PORT: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_SHUTDOWN_TIMEOUT") {
+            self.server.shutdown_timeout_secs = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_SHUTDOWN_TIMEOUT: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_MIN_FREE_DISK_MB") {
+            self.server.min_free_disk_mb = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_MIN_FREE_DISK_MB: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_ALLOWED_ORIGINS") {
+            self.server.allowed_origins = v.split(',').map(|o| o.trim().to_string()).filter(|o| !o.is_empty()).collect();
+        }
+        if let Some(b) = env_flag("VITAL_TLS") {
+            self.tls.enabled = b;
+        }
+        if let Ok(v) = env::var("VITAL_TLS_CERT") {
+            self.tls.cert = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = env::var("VITAL_TLS_KEY") {
+            self.tls.key = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = env::var("VITAL_DATA_ROOT") {
+            self.paths.data_root = PathBuf::from(v);
+        }
+        if let Ok(v) = env::var("VITAL_STATIC_DIR") {
+            self.paths.static_dir = Some(PathBuf::from(v));
+        }
+        if let Ok(v) = env::var("VITAL_PROFILES") {
+            self.profiles = v
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(|p| match p.split_once(':') {
+                    Some((id, name)) => ProfileConfig { id: id.trim().to_string(), name: name.trim().to_string() },
+                    None => ProfileConfig { id: p.to_string(), name: String::new() },
+                })
+                .collect();
+        }
+        if let Ok(v) = env::var("VITAL_LOG") {
+            self.logging.level = v;
+        }
+        if let Ok(v) = env::var("VITAL_LOG_FORMAT") {
+            self.logging.format = match v.trim().to_ascii_lowercase().as_str() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => return Err(anyhow!("unsupported VITAL_LOG_FORMAT: {}", v)),
+            };
+        }
+        if let Some(b) = env_flag("VITAL_LOG_FILE") {
+            self.logging.file = b;
+        }
+
+        if let Some(b) = env_flag("VITAL_AUTH") {
+            self.auth.enabled = b;
+        }
+        if let Ok(v) = env::var("VITAL_SESSION_TTL_HOURS") {
+            self.auth.session_ttl_hours = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_SESSION_TTL_HOURS: {}", v))?;
+        }
+
+        if let Some(b) = env_flag("VITAL_ENCRYPT") {
+            self.encryption.enabled = b;
+        }
+        if let Ok(v) = env::var("VITAL_KEY_FILE") {
+            self.encryption.key_file = Some(PathBuf::from(v));
+        }
+
+        if let Ok(v) = env::var("VITAL_MAX_BODY_MB") {
+            self.limits.max_body_mb = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_MAX_BODY_MB: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_CLIENT_WRITES_PER_MINUTE") {
+            self.limits.client_writes_per_minute = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_CLIENT_WRITES_PER_MINUTE: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_GLOBAL_WRITES_PER_MINUTE") {
+            self.limits.global_writes_per_minute = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_GLOBAL_WRITES_PER_MINUTE: {}", v))?;
+        }
+
+        if let Ok(v) = env::var("INFLUX_URL") {
+            self.influx.url = v;
+        }
+        if let Ok(v) = env::var("INFLUX_TOKEN") {
+            self.influx.token = Some(v);
+        }
+        if let Ok(v) = env::var("INFLUX_ORG") {
+            self.influx.org = Some(v);
+        }
+        if let Ok(v) = env::var("INFLUX_BUCKET") {
+            self.influx.bucket = v;
+        }
+        if env_flag("VITAL_DISABLE_INFLUX") == Some(true) {
+            self.features.influx = false;
+        }
+        let toggles = [
+            ("VITAL_FEATURE_INFLUX", &mut self.features.influx),
+            ("VITAL_FEATURE_COLOR_METRICS", &mut self.features.color_metrics),
+            ("VITAL_FEATURE_TIMELAPSE", &mut self.features.timelapse),
+            ("VITAL_FEATURE_COMPARE", &mut self.features.compare),
+        ];
+        for (key, flag) in toggles {
+            if let Some(b) = env_flag(key) {
+                *flag = b;
+            }
+        }
+
+        self.blood_pressure.apply_env()?;
+        self.image.apply_env()?;
+        self.quality.apply_env()?;
+        self.color.apply_env()?;
+        self.calibration.apply_env()?;
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.server.addr()?;
+        if self.server.port == 0 {
+            return Err(anyhow!("server.port must not be 0"));
+        }
+        for o in &self.server.allowed_origins {
+            let host = o.strip_prefix("https://").or_else(|| o.strip_prefix("http://"));
+            if host.is_none_or(|h| h.is_empty() || h.contains('/')) {
+                return Err(anyhow!("server.allowed_origins entries look like https://host[:port], got '{}'", o));
+            }
+        }
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(_), None) | (None, Some(_)) => return Err(anyhow!("tls.cert and tls.key must be set together")),
+            (Some(cert), Some(key)) => {
+                for f in [cert, key] {
+                    if !f.is_file() {
+                        return Err(anyhow!("TLS file {} does not exist", f.display()));
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+        if self.paths.data_root.as_os_str().is_empty() {
+            return Err(anyhow!("paths.data_root must not be empty"));
+        }
+        if let Some(dir) = self.paths.static_dir.as_ref().filter(|d| !d.is_dir()) {
+            return Err(anyhow!("paths.static_dir {} is not a directory", dir.display()));
+        }
+        if self.profiles.is_empty() {
+            return Err(anyhow!("at least one profile must be configured"));
+        }
+        for (i, p) in self.profiles.iter().enumerate() {
+            if !is_valid_profile_id(&p.id) {
+                return Err(anyhow!("profile id '{}' must be 1-32 lowercase letters, digits, '-' or '_', starting with a letter or digit", p.id));
+            }
+            if self.profiles[..i].iter().any(|q| q.id == p.id) {
+                return Err(anyhow!("profile id '{}' is configured twice", p.id));
+            }
+        }
+        // The default profile's entries live directly in the data root; leaving it out would hide them
+        let default_json = self.paths.data_root.join("json");
+        if !self.profiles.iter().any(|p| p.id == paths::DEFAULT_PROFILE) && has_entry_files(&default_json) {
+            return Err(anyhow!(
+                "{} holds entries of the '{}' profile, which is not configured; add a [[profiles]] entry with id = \"{}\" (it can be given any name)",
+                default_json.display(),
+                paths::DEFAULT_PROFILE,
+                paths::DEFAULT_PROFILE
+            ));
+        }
+        if self.features.influx && !(self.influx.url.starts_with("http://") || self.influx.url.starts_with("https://")) {
+            return Err(anyhow!("influx.url must start with http:// or https://, got '{}'", self.influx.url));
+        }
+        tracing_subscriber::EnvFilter::try_new(&self.logging.level).map_err(|e| anyhow!("logging.level '{}': {}", self.logging.level, e))?;
+        if self.logging.max_files == 0 {
+            return Err(anyhow!("logging.max_files must be at least 1"));
+        }
+        if let Some(f) = self.encryption.key_file.as_ref().filter(|f| !f.is_file()) {
+            return Err(anyhow!("encryption.key_file {} does not exist", f.display()));
+        }
+        if self.auth.session_ttl_hours == 0 {
+            return Err(anyhow!("auth.session_ttl_hours must be at least 1"));
+        }
+        if self.limits.max_body_mb == 0 {
+            return Err(anyhow!("limits.max_body_mb must be at least 1"));
+        }
+        self.image.validate()?;
+        self.quality.validate()?;
+        self.color.validate()?;
+        self.calibration.validate()?;
+        Ok(())
+    }
+
+    /// The named profile, or the first configured one when `id` is `None`.
+    pub fn profile(&self, id: Option<&str>) -> Result<&ProfileConfig> {
+        match id {
+            None => self.profiles.first().ok_or_else(|| anyhow!("no profiles configured")),
+            Some(id) => self.profiles.iter().find(|p| p.id == id).ok_or_else(|| {
+                let known: Vec<&str> = self.profiles.iter().map(|p| p.id.as_str()).collect();
+                anyhow!("unknown profile '{}' (configured: {})", id, known.join(", "))
+            }),
+        }
+    }
+
+    /// The configuration as TOML with secrets masked, for `config check`.
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        let mut shown = self.clone();
+        if shown.influx.token.is_some() {
+            shown.influx.token = Some("<redacted>".to_string());
+        }
+        Ok(toml::to_string_pretty(&shown)?)
+    }
+}
+
+/// True when `dir` holds at least one entry metadata (`.json`) file.
+fn has_entry_files(dir: &Path) -> bool {
+    std::fs::read_dir(dir).is_ok_and(|mut files| files.any(|f| f.is_ok_and(|f| f.path().extension().is_some_and(|e| e == "json"))))
+}
+
+/// Profile ids end up in directory names, so only a safe subset of characters is allowed.
+fn is_valid_profile_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    id.len() <= 32
+        && chars.next().is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+
+    fn temp_dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("vital-tracker-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_config(dir: &Path, toml: &str) -> PathBuf {
+        let path = dir.join("vital-tracker.toml");
+        std::fs::write(&path, toml).unwrap();
+        path
+    }
+
+    /// A configuration that passes `validate`, with its data root in a fresh directory
+    fn valid() -> Config {
+        let mut config = Config::default();
+        config.paths.data_root = temp_dir();
+        config
+    }
+
+    // The only test that touches the environment, so the variables cannot leak into others
+    #[test]
+    fn file_then_env_then_cli() {
+        let dir = temp_dir();
+        let path = write_config(&dir, "[server]\nbind = \"0.0.0.0\"\nport = 9000\n\n[logging]\nlevel = \"warn\"\n\n[influx]\nbucket = \"vitals\"\n");
+
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!((config.server.bind.as_str(), config.server.port, config.logging.level.as_str()), ("0.0.0.0", 9000, "warn"));
+        assert_eq!(config.influx.bucket, "vitals");
+
+        env::set_var("VITAL_PORT", "9100");
+        env::set_var("VITAL_LOG", "debug");
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!((config.server.bind.as_str(), config.server.port, config.logging.level.as_str()), ("0.0.0.0", 9100, "debug"));
+
+        let cli = Cli::parse_from(["vital-tracker", "--config", path.to_str().unwrap(), "--port", "9200", "--no-influx"]);
+        let config = cli.load_config().unwrap();
+        assert_eq!((config.server.port, config.logging.level.as_str()), (9200, "debug"));
+        assert!(!config.features.influx);
+
+        env::set_var("VITAL_PORT", "not a port");
+        assert!(Config::load(Some(&path)).unwrap_err().to_string().contains("VITAL_PORT"));
+        env::remove_var("VITAL_PORT");
+        env::remove_var("VITAL_LOG");
+
+        assert!(Config::load(Some(&dir.join("missing.toml"))).is_err());
+    }
+
+    #[test]
+    fn relative_paths_follow_the_config_file() {
+        let dir = temp_dir();
+        let toml = "[paths]\ndata_root = \"data\"\nstatic_dir = \"web\"\n\n[tls]\ncert = \"certs/cert.pem\"\nkey = \"/etc/vital/key.pem\"\n\n[encryption]\nkey_file = \"secret.key\"\n";
+        let config = Config::from_file(&write_config(&dir, toml)).unwrap();
+        assert_eq!(config.paths.data_root, dir.join("data"));
+        assert_eq!(config.paths.static_dir, Some(dir.join("web")));
+        assert_eq!(config.tls.cert, Some(dir.join("certs/cert.pem")));
+        assert_eq!(config.tls.key, Some(PathBuf::from("/etc/vital/key.pem")));
+        assert_eq!(config.encryption.key_file, Some(dir.join("secret.key")));
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let dir = temp_dir();
+        assert!(Config::from_file(&write_config(&dir, "[server]\nport = \"eighty\"\n")).is_err());
+        assert!(Config::from_file(&write_config(&dir, "[server\n")).is_err());
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        valid().validate().unwrap();
+    }
+
+    /// Makes one setting invalid
+    type Breakage = fn(&mut Config);
+
+    #[test]
+    fn validate_rejects_bad_values() {
+        let cases: [(&str, Breakage); 20] = [
+            ("server.bind", |c| c.server.bind = "localhost".to_string()),
+            ("server.port", |c| c.server.port = 0),
+            ("allowed_origins", |c| c.server.allowed_origins = vec!["tracker.example.org".to_string()]),
+            ("allowed_origins", |c| c.server.allowed_origins = vec!["https://tracker.example.org/app".to_string()]),
+            ("set together", |c| c.tls.cert = Some(PathBuf::from("cert.pem"))),
+            ("does not exist", |c| {
+                c.tls.cert = Some(PathBuf::from("/nonexistent/cert.pem"));
+                c.tls.key = Some(PathBuf::from("/nonexistent/key.pem"));
+            }),
+            ("data_root", |c| c.paths.data_root = PathBuf::new()),
+            ("static_dir", |c| c.paths.static_dir = Some(PathBuf::from("/nonexistent/static"))),
+            ("at least one profile", |c| c.profiles.clear()),
+            ("profile id 'Sam'", |c| c.profiles.push(ProfileConfig { id: "Sam".to_string(), name: String::new() })),
+            ("profile id '../x'", |c| c.profiles.push(ProfileConfig { id: "../x".to_string(), name: String::new() })),
+            ("configured twice", |c| c.profiles.push(c.profiles[0].clone())),
+            ("influx.url", |c| c.influx.url = "localhost:8086".to_string()),
+            ("logging.level", |c| c.logging.level = "info,=[".to_string()),
+            ("logging.max_files", |c| c.logging.max_files = 0),
+            ("encryption.key_file", |c| c.encryption.key_file = Some(PathBuf::from("/nonexistent/key"))),
+            ("session_ttl_hours", |c| c.auth.session_ttl_hours = 0),
+            ("max_body_mb", |c| c.limits.max_body_mb = 0),
+            ("jpeg_quality", |c| c.image.jpeg_quality = 0),
+            ("calibration", |c| c.calibration.enabled = true),
+        ];
+        for (expected, break_it) in cases {
+            let mut config = valid();
+            break_it(&mut config);
+            let err = config.validate().expect_err(expected).to_string();
+            assert!(err.contains(expected), "expected '{}' in '{}'", expected, err);
+        }
+    }
+
+    #[test]
+    fn influx_url_is_only_checked_when_enabled() {
+        let mut config = valid();
+        config.influx.url = "nonsense".to_string();
+        config.features.influx = false;
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn default_profile_must_stay_while_it_has_entries() {
+        let mut config = valid();
+        config.profiles = vec![ProfileConfig { id: "sam".to_string(), name: "Sam".to_string() }];
+        config.validate().unwrap();
+        let json = config.paths.data_root.join("json");
+        std::fs::create_dir_all(&json).unwrap();
+        std::fs::write(json.join("1700000000000000000.json"), "{}").unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("'default' profile"));
+    }
+}
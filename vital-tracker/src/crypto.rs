@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tokio::fs;
+
+use crate::config::EncryptionConfig;
+use crate::paths;
+
+/// Prefix of every encrypted file, followed by the nonce and the ciphertext. Files without
+/// it are plaintext, so encrypted and unencrypted files can sit side by side.
+const MAGIC: &[u8] = b"VTENC1";
+const NONCE_LEN: usize = 24;
+/// Encrypted with the derived key and kept in `encryption.json` to detect a wrong passphrase
+const CHECK_PLAINTEXT: &[u8] = b"vital-tracker key check";
+
+static CIPHER: OnceLock<XChaCha20Poly1305> = OnceLock::new();
+static ENCRYPT_WRITES: AtomicBool = AtomicBool::new(false);
+
+/// Salt for the key derivation and a sealed check value, kept in the data root.
+#[derive(Serialize, Deserialize)]
+struct KeyParams {
+    salt: String,
+    check: String,
+}
+
+/// What this run needs the key for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyUse {
+    /// Encrypt every file written (encryption enabled, or converting an archive)
+    Encrypt,
+    /// Read encrypted files but write plaintext, for `decrypt`
+    Decrypt,
+    /// Load a key only if one is configured, so leftover encrypted files stay readable
+    IfAvailable,
+}
+
+/// Where the key material comes from: a key file, `VITAL_PASSPHRASE`, or a prompt.
+fn secret(cfg: &EncryptionConfig, required: bool) -> Result<Option<Vec<u8>>> {
+    if let Some(file) = &cfg.key_file {
+        let bytes = std::fs::read(file).with_context(|| format!("reading key file {}", file.display()))?;
+        if bytes.len() < 16 {
+            return Err(anyhow!("key file {} is too short; use at least 32 random bytes", file.display()));
+        }
+        return Ok(Some(bytes));
+    }
+    if let Ok(p) = std::env::var("VITAL_PASSPHRASE") {
+        return Ok(Some(p.into_bytes()));
+    }
+    if !required {
+        return Ok(None);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("encryption is enabled but no key is available; set VITAL_PASSPHRASE or encryption.key_file"));
+    }
+    Ok(Some(rpassword::prompt_password("Data passphrase: ")?.into_bytes()))
+}
+
+fn derive(secret: &[u8], salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Argon2::default().hash_password_into(secret, salt, &mut key).map_err(|e| anyhow!("deriving key: {}", e))?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Loads the key for this run. The first time a key is set up for encrypting, its salt and
+/// check value are written to `<data_root>/encryption.json`.
+pub async fn init(cfg: &EncryptionConfig, key_use: KeyUse) -> Result<()> {
+    let encrypt_writes = key_use == KeyUse::Encrypt;
+    let Some(secret) = secret(cfg, key_use != KeyUse::IfAvailable)? else { return Ok(()) };
+    let params_path = paths::encryption_params_path();
+    let cipher = match fs::read_to_string(&params_path).await {
+        Ok(text) => {
+            let params: KeyParams = serde_json::from_str(&text).with_context(|| format!("parsing {}", params_path.display()))?;
+            let cipher = derive(&secret, &hex::decode(&params.salt)?)?;
+            let check = hex::decode(&params.check)?;
+            if decrypt_with(&cipher, &check).ok().as_deref() != Some(CHECK_PLAINTEXT) {
+                return Err(anyhow!("wrong passphrase or key file for the data in {}", paths::data_root().display()));
+            }
+            cipher
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            match key_use {
+                KeyUse::Encrypt => {}
+                KeyUse::Decrypt => return Err(anyhow!("the data in {} has never been encrypted", paths::data_root().display())),
+                KeyUse::IfAvailable => return Ok(()),
+            }
+            let mut salt = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut salt);
+            let cipher = derive(&secret, &salt)?;
+            let params = KeyParams { salt: hex::encode(salt), check: hex::encode(encrypt_with(&cipher, CHECK_PLAINTEXT)?) };
+            fs::create_dir_all(paths::data_root()).await?;
+            fs::write(&params_path, serde_json::to_vec_pretty(&params)?).await?;
+            tracing::info!(file = %params_path.display(), "set up encryption at rest; keep the passphrase or key file safe, the data cannot be recovered without it");
+            cipher
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let _ = CIPHER.set(cipher);
+    ENCRYPT_WRITES.store(encrypt_writes, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Forgets the key setup once nothing is encrypted any more, so a new passphrase can be chosen.
+pub async fn remove_params() -> Result<()> {
+    match fs::remove_file(paths::encryption_params_path()).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn encrypt_with(cipher: &XChaCha20Poly1305, plain: &[u8]) -> Result<Vec<u8>> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain).map_err(|_| anyhow!("encryption failed"))?;
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+fn decrypt_with(cipher: &XChaCha20Poly1305, bytes: &[u8]) -> Result<Vec<u8>> {
+    let body = bytes.strip_prefix(MAGIC).ok_or_else(|| anyhow!("not an encrypted file"))?;
+    if body.len() < NONCE_LEN {
+        return Err(anyhow!("encrypted file is truncated"));
+    }
+    let (nonce, sealed) = body.split_at(NONCE_LEN);
+    cipher.decrypt(XNonce::from_slice(nonce), sealed).map_err(|_| anyhow!("decryption failed: wrong key or damaged file"))
+}
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encrypts `plain` when writes are to be encrypted; otherwise returns it unchanged.
+pub fn seal(plain: Vec<u8>) -> Result<Vec<u8>> {
+    match CIPHER.get().filter(|_| ENCRYPT_WRITES.load(Ordering::Relaxed)) {
+        Some(cipher) => encrypt_with(cipher, &plain),
+        None => Ok(plain),
+    }
+}
+
+/// Decrypts an encrypted file's contents; plaintext passes through.
+pub fn open(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_encrypted(&bytes) {
+        return Ok(bytes);
+    }
+    let cipher = CIPHER.get().ok_or_else(|| anyhow!("file is encrypted; set VITAL_PASSPHRASE or encryption.key_file"))?;
+    decrypt_with(cipher, &bytes)
+}
+
+/// Encrypts `plain` regardless of the write setting, for converting an archive.
+pub fn encrypt(plain: &[u8]) -> Result<Vec<u8>> {
+    encrypt_with(CIPHER.get().ok_or_else(|| anyhow!("no encryption key loaded"))?, plain)
+}
+
+pub async fn read(path: &Path) -> Result<Vec<u8>> {
+    let bytes = fs::read(path).await.with_context(|| format!("reading {}", path.display()))?;
+    open(bytes).with_context(|| format!("reading {}", path.display()))
+}
+
+pub fn read_blocking(path: &Path) -> Result<Vec<u8>> {
+    let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    open(bytes).with_context(|| format!("reading {}", path.display()))
+}
+
+pub async fn write(path: &Path, plain: Vec<u8>) -> Result<()> {
+    fs::write(path, seal(plain)?).await.with_context(|| format!("writing {}", path.display()))
+}
+
+/// Loads a key derived from `secret` for tests, without key files or `encryption.json`.
+#[cfg(test)]
+pub fn init_for_tests(secret: &[u8]) {
+    let _ = CIPHER.set(derive(secret, b"vital-tracker-test-salt").expect("derive test key"));
+}
+
+/// Switches encryption of written files on or off for tests.
+#[cfg(test)]
+pub fn set_encrypt_writes_for_tests(on: bool) {
+    ENCRYPT_WRITES.store(on, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: &[u8] = b"0123456789abcdef";
+
+    #[test]
+    fn round_trip() {
+        let cipher = derive(b"correct horse", SALT).unwrap();
+        let sealed = encrypt_with(&cipher, b"{\"sys\":120}").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert_eq!(sealed.len(), MAGIC.len() + NONCE_LEN + 11 + 16);
+        assert_eq!(decrypt_with(&cipher, &sealed).unwrap(), b"{\"sys\":120}");
+        // A fresh nonce every time
+        assert_ne!(sealed, encrypt_with(&cipher, b"{\"sys\":120}").unwrap());
+    }
+
+    #[test]
+    fn same_secret_and_salt_derive_the_same_key() {
+        let sealed = encrypt_with(&derive(b"correct horse", SALT).unwrap(), b"photo").unwrap();
+        assert_eq!(decrypt_with(&derive(b"correct horse", SALT).unwrap(), &sealed).unwrap(), b"photo");
+        assert!(decrypt_with(&derive(b"correct horse", b"fedcba9876543210").unwrap(), &sealed).is_err());
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let sealed = encrypt_with(&derive(b"correct horse", SALT).unwrap(), b"photo").unwrap();
+        assert!(decrypt_with(&derive(b"battery staple", SALT).unwrap(), &sealed).is_err());
+    }
+
+    #[test]
+    fn tampered_or_truncated_files_are_rejected() {
+        let cipher = derive(b"correct horse", SALT).unwrap();
+        let sealed = encrypt_with(&cipher, b"photo").unwrap();
+        for i in [MAGIC.len(), MAGIC.len() + NONCE_LEN, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1;
+            assert!(decrypt_with(&cipher, &tampered).is_err(), "byte {} flipped", i);
+        }
+        assert!(decrypt_with(&cipher, &sealed[..MAGIC.len() + 10]).is_err());
+        assert!(decrypt_with(&cipher, &sealed[..sealed.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn plaintext_passes_through() {
+        let plain = b"{\"sys\":120,\"dia\":80}".to_vec();
+        assert!(!is_encrypted(&plain));
+        assert_eq!(open(plain.clone()).unwrap(), plain);
+        assert!(decrypt_with(&derive(b"correct horse", SALT).unwrap(), &plain).is_err());
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::{anyhow, Result};
 use chrono::Utc;
 use reqwest::Client;
-use std::env;
+
+use crate::config::InfluxConfig;
 
 pub struct InfluxClient {
     url: String,
@@ -12,19 +13,14 @@ pub struct InfluxClient {
 }
 
 impl InfluxClient {
-    pub fn from_env() -> Result<Self> {
-        let url = env::var("INFLUX_URL").unwrap_or_else(|_| "http://localhost:8086".to_string());
-        let token = env::var("INFLUX_TOKEN").ok();
-        let org = env::var("INFLUX_ORG").ok();
-        let bucket = env::var("INFLUX_BUCKET").unwrap_or_else(|_| "default".to_string());
-
-        Ok(InfluxClient {
-            url,
-            token,
-            org,
-            bucket,
+    pub fn new(config: &InfluxConfig) -> Self {
+        InfluxClient {
+            url: config.url.trim_end_matches('/').to_string(),
+            token: config.token.clone(),
+            org: config.org.clone(),
+            bucket: config.bucket.clone(),
             client: Client::new(),
-        })
+        }
     }
 
     pub fn org(&self) -> Option<&String> {
@@ -46,8 +42,8 @@ impl InfluxClient {
             timestamp = timestamp
         );
 
-        let write_url = if self.org.is_some() {
-            format!("{}/api/v2/write?org={}&bucket={}&precision=ns", self.url, self.org.as_ref().unwrap(), self.bucket)
+        let write_url = if let Some(org) = &self.org {
+            format!("{}/api/v2/write?org={}&bucket={}&precision=ns", self.url, org, self.bucket)
         } else {
             format!("{}/write?db={}", self.url, self.bucket)
         };
@@ -69,19 +65,21 @@ impl InfluxClient {
         Ok(())
     }
 
-    /// Writes full vital entry storing numeric fields and photo path
-    pub async fn write_entry(&self, sys: i64, dia: i64, pulse: i64, temp_c: f64, photo_path: &str) -> Result<()> {
-        let now = Utc::now();
-        let timestamp = now.timestamp_nanos_opt().unwrap_or(now.timestamp() * 1_000_000_000);
-
-        let escaped = photo_path.replace('"', "\\\"");
-        let line = format!(
-            "vital_entry sys={},dia={},pulse={},temp_c={},photo=\"{}\" {}",
-            sys, dia, pulse, temp_c, escaped, timestamp
-        );
-
-        let write_url = if self.org.is_some() {
-            format!("{}/api/v2/write?org={}&bucket={}&precision=ns", self.url, self.org.as_ref().unwrap(), self.bucket)
+    /// Writes a vital entry as one point at the entry's own timestamp, so an edited entry
+    /// overwrites its earlier point as long as its tags stay the same. `fields` are the numeric
+    /// readings and `text` string fields such as the photo path; tag values must not need escaping.
+    pub async fn write_entry(&self, tags: &[(&str, &str)], timestamp_nanos: i128, fields: &[(String, f64)], text: &[(&str, &str)]) -> Result<()> {
+        let numeric = fields
+            .iter()
+            .filter(|(_, v)| v.is_finite())
+            .map(|(k, v)| format!("{}={}", k.replace([',', '=', ' '], "_"), v));
+        let strings = text.iter().map(|(k, v)| format!("{}=\"{}\"", k, v.replace('"', "\\\"")));
+        let fields: Vec<String> = numeric.chain(strings).collect();
+        let tags: String = tags.iter().map(|(k, v)| format!(",{}={}", k, v)).collect();
+        let line = format!("vital_entry{} {} {}", tags, fields.join(","), timestamp_nanos);
+
+        let write_url = if let Some(org) = &self.org {
+            format!("{}/api/v2/write?org={}&bucket={}&precision=ns", self.url, org, self.bucket)
         } else {
             format!("{}/write?db={}", self.url, self.bucket)
         };
@@ -102,8 +100,8 @@ impl InfluxClient {
 
     /// Simple compatibility query using InfluxQL returning csv text
     pub async fn query_influxql(&self, q: &str) -> Result<String> {
-        if self.org.is_some() {
-            let url = format!("{}/api/v2/query?org={}", self.url, self.org.as_ref().unwrap());
+        if let Some(org) = &self.org {
+            let url = format!("{}/api/v2/query?org={}", self.url, org);
             let mut req = self.client.post(&url)
                 .header("Content-Type", "application/vnd.flux")
                 .header("Accept", "application/csv")
@@ -117,7 +115,7 @@ impl InfluxClient {
             if !status.is_success() {
                 return Err(anyhow!("Influx query failed: {} - {}", status, body));
             }
-            return Ok(body);
+            Ok(body)
         } else {
             let url = format!("{}/query?db={}", self.url, self.bucket);
             let mut req = self.client.post(&url).form(&[("q", q)]);
@@ -130,7 +128,16 @@ impl InfluxClient {
             if !status.is_success() {
                 return Err(anyhow!("Influx query failed: {} - {}", status, body));
             }
-            return Ok(body);
+            Ok(body)
+        }
+    }
+
+    /// Calls `/ping`, which both 1.x and 2.x answer without authentication.
+    pub async fn ping(&self, timeout: std::time::Duration) -> Result<()> {
+        let resp = self.client.get(format!("{}/ping", self.url)).timeout(timeout).send().await.map_err(|e| anyhow!(e))?;
+        if !resp.status().is_success() {
+            return Err(anyhow!("Influx ping failed: {}", resp.status()));
         }
+        Ok(())
     }
 }
@@ -0,0 +1,629 @@
+use anyhow::Result;
+use chrono::{Local, NaiveDate, TimeZone, Utc};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::Instant;
+use tokio::fs;
+
+use crate::audit::{self, Action, Event};
+use crate::blobstore::BlobStore;
+use crate::bp::{Classification, Derived, Guideline};
+use crate::config::Config;
+use crate::crypto;
+use crate::db::influx::InfluxClient;
+use crate::health;
+use crate::imaging;
+use crate::imaging::calibration::{CalibrationOptions, CalibrationResult};
+use crate::imaging::color::{ColorMetrics, ColorOptions};
+use crate::imaging::output::{self, ImageOptions};
+use crate::imaging::quality::{self, PhotoQuality, QualityMode, QualityOptions};
+use crate::metrics::METRICS;
+use crate::paths::{self, ProfileDirs};
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Entry {
+    pub path: String,
+    pub sys: i64,
+    pub dia: i64,
+    pub pulse: i64,
+    pub temp_c: f64,
+    pub temp_jaw: Option<f64>,
+    pub temp_room: Option<f64>,
+    #[serde(alias = "pain_hr")]
+    pub pain: Option<i64>,
+    pub timestamp_nanos: i128,
+    /// Individual view photos keyed by view name; empty for entries that predate per-view storage
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub views: BTreeMap<String, String>,
+    /// Sharpness, brightness and perceptual hash per view
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quality: BTreeMap<String, PhotoQuality>,
+    /// Lab colour and redness of each tracked view's region of interest
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub color: BTreeMap<String, ColorMetrics>,
+    /// Grey-card white balance applied to each view before storage, when calibration is enabled
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub calibration: BTreeMap<String, CalibrationResult>,
+}
+
+impl Entry {
+    /// Numeric Influx fields: the readings, then ones derived from the photos, e.g. `redness_neck`.
+    pub fn influx_fields(&self) -> Vec<(String, f64)> {
+        let mut fields = vec![
+            ("sys".to_string(), self.sys as f64),
+            ("dia".to_string(), self.dia as f64),
+            ("pulse".to_string(), self.pulse as f64),
+            ("temp_c".to_string(), self.temp_c),
+        ];
+        if let Some(derived) = self.derived() {
+            fields.push(("map".to_string(), derived.map));
+            fields.push(("pulse_pressure".to_string(), derived.pulse_pressure));
+            fields.extend(derived.shock_index.map(|si| ("shock_index".to_string(), si)));
+        }
+        for (view, c) in &self.color {
+            fields.push((format!("lab_a_{}", view), c.a));
+            fields.push((format!("redness_{}", view), c.redness));
+        }
+        fields
+    }
+
+    /// False for legacy and fallback entries saved without blood pressure readings.
+    pub fn has_bp(&self) -> bool {
+        self.sys > 0 && self.dia > 0
+    }
+
+    /// The reading's category; `None` when the entry has no readings.
+    pub fn classify(&self, guideline: Guideline) -> Option<Classification> {
+        self.has_bp().then(|| guideline.classify(self.sys as f64, self.dia as f64))
+    }
+
+    /// MAP, pulse pressure and shock index of the reading.
+    pub fn derived(&self) -> Option<Derived> {
+        Derived::from_reading(self.sys as f64, self.dia as f64, self.pulse as f64)
+    }
+
+    /// The entry as JSON for `/entries` and exports, with the values derived from it.
+    /// Nanosecond timestamps exceed JavaScript's integer precision, so an exact `id` is added too.
+    pub fn to_annotated_json(&self, guideline: Guideline) -> serde_json::Value {
+        let mut v = serde_json::to_value(self).unwrap_or_default();
+        v["id"] = self.timestamp_nanos.to_string().into();
+        v["bp_class"] = serde_json::to_value(self.classify(guideline)).unwrap_or_default();
+        let derived = self.derived();
+        v["map"] = derived.map(|d| d.map).into();
+        v["pulse_pressure"] = derived.map(|d| d.pulse_pressure).into();
+        v["shock_index"] = derived.and_then(|d| d.shock_index).into();
+        v
+    }
+
+    /// Every photo URL the entry refers to: the composite first, then the views.
+    pub fn photo_urls(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.path).filter(|p| !p.is_empty()).chain(self.views.values())
+    }
+}
+
+/// Corrected readings for a stored entry; fields left out keep their value.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EntryChanges {
+    pub sys: Option<i64>,
+    pub dia: Option<i64>,
+    pub pulse: Option<i64>,
+    #[serde(alias = "temp")]
+    pub temp_c: Option<f64>,
+    pub temp_jaw: Option<f64>,
+    pub temp_room: Option<f64>,
+    #[serde(alias = "pain_hr")]
+    pub pain: Option<i64>,
+}
+
+impl EntryChanges {
+    fn apply(self, e: &mut Entry) {
+        e.sys = self.sys.unwrap_or(e.sys);
+        e.dia = self.dia.unwrap_or(e.dia);
+        e.pulse = self.pulse.unwrap_or(e.pulse);
+        e.temp_c = self.temp_c.unwrap_or(e.temp_c);
+        e.temp_jaw = self.temp_jaw.or(e.temp_jaw);
+        e.temp_room = self.temp_room.or(e.temp_room);
+        e.pain = self.pain.or(e.pain);
+    }
+}
+
+/// Readings for a new entry, as submitted by the web form or the `add` command.
+#[derive(Debug, Clone, Default)]
+pub struct NewEntry {
+    pub sys: Option<i64>,
+    pub dia: Option<i64>,
+    pub pulse: Option<i64>,
+    pub temp: Option<f64>,
+    pub temp_jaw: Option<f64>,
+    pub temp_room: Option<f64>,
+    pub pain: Option<i64>,
+    /// Raw photo bytes in `imaging::VIEW_NAMES` order
+    pub photos: [Option<Vec<u8>>; 4],
+}
+
+/// Checks that the required readings are present and physically plausible.
+pub fn validate_vitals(e: &Entry) -> Result<(), String> {
+    if !(40..=300).contains(&e.sys) {
+        return Err(format!("systolic pressure {} is out of range (40-300)", e.sys));
+    }
+    if !(20..=200).contains(&e.dia) {
+        return Err(format!("diastolic pressure {} is out of range (20-200)", e.dia));
+    }
+    if e.dia >= e.sys {
+        return Err(format!("diastolic pressure {} must be below systolic {}", e.dia, e.sys));
+    }
+    if !(20..=250).contains(&e.pulse) {
+        return Err(format!("pulse {} is out of range (20-250)", e.pulse));
+    }
+    for (name, t) in [("temperature", Some(e.temp_c)), ("jaw temperature", e.temp_jaw)] {
+        if let Some(t) = t.filter(|t| !(25.0..=45.0).contains(t)) {
+            return Err(format!("{} {} is out of range (25-45 °C)", name, t));
+        }
+    }
+    if let Some(t) = e.temp_room.filter(|t| !(-30.0..=60.0).contains(t)) {
+        return Err(format!("room temperature {} is out of range (-30-60 °C)", t));
+    }
+    if let Some(p) = e.pain.filter(|p| !(0..=10).contains(p)) {
+        return Err(format!("pain {} is out of range (0-10)", p));
+    }
+    Ok(())
+}
+
+/// Why an entry was not stored.
+#[derive(Debug)]
+pub enum AddError {
+    /// Missing or implausible readings
+    Invalid(String),
+    /// Photos failed the quality checks while they are set to reject
+    Rejected(Vec<String>),
+    /// The data disk is below `server.min_free_disk_mb`
+    NoSpace(String),
+    Failed(anyhow::Error),
+}
+
+impl std::fmt::Display for AddError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddError::Invalid(msg) => write!(f, "{}", msg),
+            AddError::Rejected(issues) => write!(f, "photo quality check failed:\n{}", issues.join("\n")),
+            AddError::NoSpace(msg) => write!(f, "{}", msg),
+            AddError::Failed(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for AddError {
+    fn from(e: anyhow::Error) -> Self {
+        AddError::Failed(e)
+    }
+}
+
+/// An entry brought back from the audit log, and any photos `gc` had already removed.
+pub struct Restored {
+    pub entry: Entry,
+    pub missing_photos: Vec<String>,
+}
+
+/// A stored entry and anything the user should know about its photos.
+pub struct Added {
+    pub entry: Entry,
+    pub warnings: Vec<String>,
+}
+
+/// Encoded composite and per-view photos, ready to be stored.
+struct EncodedPhotos {
+    composite: Vec<u8>,
+    views: Vec<(String, Vec<u8>)>,
+}
+
+/// Paths produced by storing an entry's photos.
+struct SavedPhotos {
+    /// Public URL of the composite strip
+    composite: String,
+    views: BTreeMap<String, String>,
+    timestamp: i128,
+}
+
+/// Entry metadata and photo storage, shared by the HTTP handlers and the command line.
+pub struct EntryStore {
+    /// Id of the profile whose entries this store holds
+    pub profile: String,
+    pub dirs: ProfileDirs,
+    pub image: ImageOptions,
+    pub quality: QualityOptions,
+    pub color: ColorOptions,
+    pub calibration: CalibrationOptions,
+    pub color_metrics: bool,
+    /// Blood pressure categories for the Influx `bp_class` field
+    pub guideline: Guideline,
+    /// Photos are refused below this much free space on the data disk
+    pub min_free_disk_mb: u64,
+    pub blobs: BlobStore,
+}
+
+impl EntryStore {
+    /// Opens a profile's store under the data root `paths::init` was given, creating
+    /// directories as needed.
+    pub async fn open(config: &Config, profile: &str) -> Result<Self> {
+        let dirs = ProfileDirs::new(profile);
+        dirs.ensure().await?;
+        Ok(EntryStore {
+            profile: profile.to_string(),
+            blobs: BlobStore::open(dirs.clone()).await?,
+            dirs,
+            image: config.image.clone(),
+            quality: config.quality.clone(),
+            color: config.color.clone(),
+            calibration: config.calibration.clone(),
+            color_metrics: config.features.color_metrics,
+            guideline: config.blood_pressure.guideline,
+            min_free_disk_mb: config.server.min_free_disk_mb,
+        })
+    }
+
+    /// Validates the readings, runs the photo pipeline (quality, calibration, colour, encoding)
+    /// and stores the photos and metadata. `actor` is recorded in the audit log.
+    pub async fn add(&self, input: NewEntry, actor: &str) -> Result<Added, AddError> {
+        let (Some(sys), Some(dia), Some(pulse), Some(temp)) = (input.sys, input.dia, input.pulse, input.temp) else {
+            return Err(AddError::Invalid("Missing numeric fields".to_string()));
+        };
+        let mut meta = Entry { sys, dia, pulse, temp_c: temp, temp_jaw: input.temp_jaw, temp_room: input.temp_room, pain: input.pain, ..Default::default() };
+        validate_vitals(&meta).map_err(AddError::Invalid)?;
+        if input.photos.iter().all(Option::is_none) {
+            // Readings only, e.g. from `vital-tracker add` in a cron job
+            meta.timestamp_nanos = now_nanos();
+            write_entry_meta(&self.dirs, &meta).await?;
+            audit::record(actor, self.event(Action::Create).entry(meta.timestamp_nanos.to_string()).after(&meta)).await;
+            METRICS.entry_added(&self.profile);
+            return Ok(Added { entry: meta, warnings: Vec::new() });
+        }
+
+        self.ensure_disk_space().await?;
+        let (views, decode_time) = imaging::decode_views(input.photos).await?;
+
+        // Measure every view before anything is written so bad captures can be refused.
+        // Quality is judged on the raw capture; colour on the white-balanced one.
+        let color_opts = if self.color_metrics { self.color.clone() } else { ColorOptions { regions: BTreeMap::new() } };
+        let cal_opts = self.calibration.clone();
+        let (views, photo_quality, photo_color, photo_calibration, quality_time) = tokio::task::spawn_blocking(move || {
+            let start = Instant::now();
+            let q = imaging::assess_views(&views);
+            let (views, cal) = imaging::calibrate_views(views, &cal_opts);
+            let c = imaging::measure_colors(&views, &color_opts);
+            (views, q, c, cal, start.elapsed())
+        })
+        .await
+        .map_err(anyhow::Error::from)?;
+        let issues = quality::find_issues(&photo_quality, &self.quality);
+        if self.quality.mode == QualityMode::Reject && !issues.is_empty() {
+            return Err(AddError::Rejected(issues));
+        }
+
+        let timings = imaging::StageTimings { decode: decode_time, quality: quality_time, ..Default::default() };
+        let saved = self.combine_and_save_images(views, timings).await?;
+        meta.path = saved.composite;
+        meta.timestamp_nanos = saved.timestamp;
+        meta.views = saved.views;
+        meta.quality = photo_quality;
+        meta.color = photo_color;
+        meta.calibration = photo_calibration;
+        write_entry_meta(&self.dirs, &meta).await?;
+        audit::record(actor, self.event(Action::Create).entry(meta.timestamp_nanos.to_string()).after(&meta)).await;
+
+        let mut warnings: Vec<String> = Vec::new();
+        if self.quality.mode == QualityMode::Warn {
+            warnings.extend(issues);
+        }
+        for (view, cal) in &meta.calibration {
+            if let Some(note) = cal.note.as_ref().filter(|_| !cal.applied) {
+                warnings.push(format!("{} photo not colour-calibrated: {}", view, note));
+            }
+        }
+        METRICS.entry_added(&self.profile);
+        Ok(Added { entry: meta, warnings })
+    }
+
+    /// An audit event about this store's profile.
+    fn event(&self, action: Action) -> Event {
+        Event::new(action).profile(&self.profile)
+    }
+
+    /// Refuses new photos when the data disk is nearly full, so a runaway client cannot fill it.
+    async fn ensure_disk_space(&self) -> Result<(), AddError> {
+        match health::available_mb().await {
+            Ok(free) if free < self.min_free_disk_mb => {
+                tracing::warn!(free_mb = free, min_free_mb = self.min_free_disk_mb, "refusing photos, disk nearly full");
+                Err(AddError::NoSpace(format!("only {} MB free on the data disk (minimum {} MB); photos are not accepted until space is freed", free, self.min_free_disk_mb)))
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::warn!(error = %e, "cannot read free disk space");
+                Ok(())
+            }
+        }
+    }
+
+    async fn combine_and_save_images(&self, views: [Option<DynamicImage>; 4], timings: imaging::StageTimings) -> Result<SavedPhotos> {
+        let timestamp = now_nanos();
+        let opts = self.image.clone();
+        let ext = opts.format.extension();
+        let filename = format!("{}.{}", timestamp, ext);
+
+        // Scaling and encoding are CPU bound; keep them off the async executor
+        let (mut timings, encoded) = tokio::task::spawn_blocking(move || -> Result<(imaging::StageTimings, EncodedPhotos)> {
+            let mut timings = timings;
+            let imgbuf = imaging::compose_strip(&views, &mut timings)?;
+            let start = Instant::now();
+            let imgbuf = output::fit_within(imgbuf, opts.max_dimension);
+            timings.resize += start.elapsed();
+            let start = Instant::now();
+            let bytes = output::encode(&imgbuf, &opts)?;
+
+            // Keep each view on its own as well so time-lapses and comparisons can use it
+            let mut view_bytes = Vec::new();
+            for (name, view) in imaging::VIEW_NAMES.iter().zip(&views) {
+                if let Some(img) = view {
+                    let rgb = output::fit_within(img.to_rgb8(), opts.max_dimension);
+                    view_bytes.push((name.to_string(), output::encode(&rgb, &opts)?));
+                }
+            }
+            timings.encode = start.elapsed();
+            Ok((timings, EncodedPhotos { composite: bytes, views: view_bytes }))
+        }).await??;
+
+        let start = Instant::now();
+        let composite = format!("/photos/{}", filename);
+        self.blobs.put(&composite, &encoded.composite).await?;
+        let mut view_paths = BTreeMap::new();
+        for (name, bytes) in encoded.views {
+            let url = format!("/photos/views/{}_{}.{}", timestamp, name, ext);
+            self.blobs.put(&url, &bytes).await?;
+            view_paths.insert(name, url);
+        }
+        timings.encode += start.elapsed();
+        tracing::info!(file = %filename, timings = %timings, "stored photos");
+        METRICS.observe_image(&timings);
+
+        Ok(SavedPhotos { composite, views: view_paths, timestamp })
+    }
+
+    /// Stores an entry exported from another installation. Photos are taken from `photos_dir`
+    /// (laid out like /photos) when given; entries whose timestamp already exists are skipped.
+    /// Returns false when the entry was already present.
+    pub async fn import(&self, entry: &Entry, photos_dir: Option<&Path>, actor: &str) -> Result<bool, AddError> {
+        // Legacy entries without readings are exported as they are, so they come back unchecked
+        if entry.has_bp() {
+            validate_vitals(entry).map_err(AddError::Invalid)?;
+        }
+        // Stored photo URLs are later joined onto the photos directory, copied or not
+        if let Some(url) = entry.photo_urls().find(|u| !paths::is_contained(u.trim_start_matches("/photos/"))) {
+            return Err(AddError::Invalid(format!("unsafe photo path {}", url)));
+        }
+        if fs::try_exists(self.dirs.json_meta_path(&entry.timestamp_nanos.to_string())).await.unwrap_or(false) {
+            return Ok(false);
+        }
+        if let Some(dir) = photos_dir {
+            self.ensure_disk_space().await?;
+            for url in entry.photo_urls() {
+                let name = url.trim_start_matches("/photos/");
+                let bytes = fs::read(dir.join(name)).await.map_err(|e| AddError::Failed(anyhow::anyhow!("reading {}: {}", dir.join(name).display(), e)))?;
+                self.blobs.put(url, &bytes).await?;
+            }
+        }
+        write_entry_meta(&self.dirs, entry).await?;
+        audit::record(actor, self.event(Action::Import).entry(entry.timestamp_nanos.to_string()).after(entry)).await;
+        Ok(true)
+    }
+
+    /// Applies corrected readings to a stored entry. Returns `None` when there is no such entry.
+    pub async fn edit(&self, ts: &str, changes: EntryChanges, actor: &str) -> Result<Option<Entry>, AddError> {
+        let Some(before) = read_entry(&self.dirs, ts).await? else { return Ok(None) };
+        let mut after = before.clone();
+        changes.apply(&mut after);
+        validate_vitals(&after).map_err(AddError::Invalid)?;
+        write_entry_meta(&self.dirs, &after).await?;
+        audit::record(actor, self.event(Action::Edit).entry(ts).before(&before).after(&after)).await;
+        Ok(Some(after))
+    }
+
+    /// Brings back a deleted entry from its last delete record in the audit log, relinking
+    /// photos whose blobs are still there. Returns `None` when the log has no such delete.
+    pub async fn restore(&self, ts: &str, actor: &str) -> Result<Option<Restored>, AddError> {
+        if read_entry(&self.dirs, ts).await?.is_some() {
+            return Err(AddError::Invalid(format!("entry {} exists; only deleted entries can be restored", ts)));
+        }
+        let Some(deleted) = audit::last_delete(&self.profile, ts).await? else { return Ok(None) };
+        let entry: Entry = serde_json::from_value(deleted.before.unwrap_or_default()).map_err(|e| anyhow::anyhow!("audit record {} has no usable entry: {}", deleted.seq, e))?;
+        let mut missing_photos = Vec::new();
+        for url in entry.photo_urls() {
+            let name = url.trim_start_matches("/photos/");
+            let relinked = match deleted.photos.get(name) {
+                Some(hash) => self.blobs.relink(name, hash).await?,
+                None => false,
+            };
+            if !relinked {
+                missing_photos.push(url.clone());
+            }
+        }
+        write_entry_meta(&self.dirs, &entry).await?;
+        let mut event = self.event(Action::Restore).entry(ts).after(&entry);
+        if !missing_photos.is_empty() {
+            event = event.detail(format!("photos already removed: {}", missing_photos.join(", ")));
+        }
+        audit::record(actor, event).await;
+        Ok(Some(Restored { entry, missing_photos }))
+    }
+
+    /// Removes an entry's metadata and releases its photos. The audit record keeps the entry
+    /// and its blob hashes so it can be restored until the next `gc`.
+    pub async fn delete(&self, ts: &str, actor: &str) {
+        let before = read_entry(&self.dirs, ts).await.unwrap_or_else(|e| {
+            tracing::warn!(entry_id = ts, "could not read entry before deleting: {:#}", e);
+            None
+        });
+        // Derive file paths from timestamp base; the photo may have been stored in any supported format
+        let meta_new = self.dirs.json_meta_path(ts);
+        let meta_legacy = self.dirs.photos_dir().join(format!("{}.json", ts));
+
+        // Helper to ignore NotFound errors
+        async fn rm(p: &Path) {
+            if let Err(e) = fs::remove_file(p).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!(path = %p.display(), error = %e, "delete failed");
+                }
+            }
+        }
+
+        rm(&meta_new).await;
+        rm(&meta_legacy).await;
+        // Blobs shared with other entries stay; unreferenced ones go at the next /blobs/gc
+        let composite_prefix = format!("{}.", ts);
+        let views_prefix = format!("views/{}_", ts);
+        let released = self.blobs.release_where(|n| n.starts_with(&composite_prefix) || n.starts_with(&views_prefix)).await.unwrap_or_else(|e| {
+            tracing::warn!(entry_id = ts, error = %e, "releasing photos failed");
+            BTreeMap::new()
+        });
+        // Files written before content-addressed storage
+        for ext in output::PHOTO_EXTENSIONS {
+            rm(&self.dirs.photos_dir().join(format!("{}.{}", ts, ext))).await;
+            for view in imaging::VIEW_NAMES {
+                rm(&self.dirs.views_dir().join(format!("{}_{}.{}", ts, view, ext))).await;
+            }
+        }
+        if let Some(entry) = before {
+            audit::record(actor, self.event(Action::Delete).entry(ts).before(&entry).photos(released)).await;
+        }
+    }
+}
+
+fn now_nanos() -> i128 {
+    let now = Utc::now();
+    now.timestamp() as i128 * 1_000_000_000i128 + now.timestamp_subsec_nanos() as i128
+}
+
+async fn write_entry_meta(dirs: &ProfileDirs, meta: &Entry) -> Result<()> {
+    let meta_path = dirs.json_meta_path(&meta.timestamp_nanos.to_string());
+    crypto::write(&meta_path, serde_json::to_vec(meta)?).await?;
+    Ok(())
+}
+
+/// Entry ids are nanosecond timestamps; anything else must not reach a file path.
+pub fn is_valid_id(ts: &str) -> bool {
+    !ts.is_empty() && ts.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// A single entry's metadata, from the json directory or next to a legacy photo.
+pub async fn read_entry(dirs: &ProfileDirs, ts: &str) -> Result<Option<Entry>> {
+    for path in [dirs.json_meta_path(ts), dirs.photos_dir().join(format!("{}.json", ts))] {
+        if fs::try_exists(&path).await? {
+            return Ok(Some(serde_json::from_slice(&crypto::read(&path).await?)?));
+        }
+    }
+    Ok(None)
+}
+
+/// Number of entry metadata files, without parsing them.
+pub async fn count_entries(dirs: &ProfileDirs) -> usize {
+    let mut count = 0;
+    if let Ok(mut files) = fs::read_dir(dirs.json_dir()).await {
+        while let Ok(Some(entry)) = files.next_entry().await {
+            if entry.path().extension().and_then(|e| e.to_str()) == Some("json") {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Reads every stored entry, falling back to a minimal record for photos without readable metadata.
+pub async fn read_all_entries(dirs: &ProfileDirs) -> Vec<Entry> {
+    let mut out: Vec<Entry> = Vec::new();
+    let mut seen: BTreeSet<String> = BTreeSet::new();
+    // Every entry stored since photos moved to the blob store has its metadata under data/json
+    if let Ok(mut files) = fs::read_dir(dirs.json_dir()).await {
+        while let Ok(Some(entry)) = files.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match crypto::read(&path).await {
+                Ok(j) => {
+                    if let Ok(entry_meta) = serde_json::from_slice::<Entry>(&j) {
+                        seen.insert(entry_meta.timestamp_nanos.to_string());
+                        out.push(entry_meta);
+                    }
+                }
+                Err(e) => tracing::warn!("skipping entry: {:#}", e),
+            }
+        }
+    }
+    // Older photo files on disk: metadata may sit next to the photo or be missing entirely
+    if let Ok(mut files) = fs::read_dir(dirs.photos_dir()).await {
+        while let Ok(Some(entry)) = files.next_entry().await {
+            if let Ok(md) = entry.metadata().await {
+                if md.is_file() {
+                    if let Some(fname) = entry.file_name().to_str() {
+                        // look for .json metadata file matching the image name
+                        let fpath = std::path::Path::new(fname);
+                        let is_photo = fpath.extension().and_then(|e| e.to_str()).map(|e| output::PHOTO_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str())).unwrap_or(false);
+                        if is_photo {
+                            let base = fpath.file_stem().and_then(|b| b.to_str()).unwrap_or_default();
+                            if seen.contains(base) {
+                                continue;
+                            }
+                            let meta_path_new = dirs.json_meta_path(base);
+                            let meta_path_legacy = dirs.photos_dir().join(format!("{}.json", base));
+                            // Try new location first, then legacy next-to-photo JSON
+                            let meta_json_res = match crypto::read(&meta_path_new).await {
+                                Ok(j) => Ok(j),
+                                Err(_) => crypto::read(&meta_path_legacy).await,
+                            };
+                            if let Ok(j) = meta_json_res {
+                                if let Ok(entry_meta) = serde_json::from_slice::<Entry>(&j) {
+                                    out.push(entry_meta);
+                                } else {
+                                    // fallback: return minimal entry
+                                    let rel = format!("/photos/{}", fname);
+                                    out.push(Entry { path: rel, timestamp_nanos: base.parse::<i128>().unwrap_or(0), ..Default::default() });
+                                }
+                            } else {
+                                let rel = format!("/photos/{}", fname);
+                                out.push(Entry { path: rel, timestamp_nanos: base.parse::<i128>().unwrap_or(0), ..Default::default() });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Writes the entry to Influx tagged with its profile, including the derived colour fields.
+/// The blood pressure category is a field rather than a tag: tags are part of the series, so
+/// an edit that changes the category would otherwise leave the old point behind.
+pub async fn send_to_influx(client: &InfluxClient, store: &EntryStore, e: &Entry) -> Result<()> {
+    let photo = if e.path.is_empty() { String::new() } else { store.dirs.photo_file_path(&e.path).display().to_string() };
+    let mut text = vec![("photo", photo.as_str())];
+    text.extend(e.classify(store.guideline).map(|c| ("bp_class", c.category.as_str())));
+    let result = client.write_entry(&[("profile", store.profile.as_str())], e.timestamp_nanos, &e.influx_fields(), &text).await;
+    METRICS.influx_write(result.is_ok());
+    result
+}
+
+pub fn parse_day(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").map_err(|_| anyhow::anyhow!("invalid date '{}', expected YYYY-MM-DD", s))
+}
+
+/// Nanosecond timestamp of local midnight at the start of `day`.
+pub fn local_day_start_nanos(day: NaiveDate) -> i128 {
+    let midnight = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+    let dt = Local.from_local_datetime(&midnight).earliest().map(|d| d.with_timezone(&Utc)).unwrap_or_else(|| Utc.from_utc_datetime(&midnight));
+    dt.timestamp() as i128 * 1_000_000_000i128
+}
+
+pub fn local_time_label(timestamp_nanos: i128) -> String {
+    Local.timestamp_nanos(timestamp_nanos as i64).format("%Y-%m-%d %H:%M").to_string()
+}
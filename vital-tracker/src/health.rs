@@ -0,0 +1,128 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+use crate::blobstore::BlobStore;
+use crate::db::influx::InfluxClient;
+use crate::paths;
+
+const INFLUX_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Fail,
+    Disabled,
+}
+
+/// Outcome of one readiness check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub status: CheckStatus,
+    /// A failing required check makes the whole service not ready
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+}
+
+impl Check {
+    fn new(required: bool, started: Instant, result: Result<String, String>) -> Self {
+        let (status, detail) = match result {
+            Ok(d) => (CheckStatus::Ok, d),
+            Err(d) => (CheckStatus::Fail, d),
+        };
+        Check {
+            status,
+            required,
+            detail: Some(detail).filter(|d| !d.is_empty()),
+            latency_ms: Some(started.elapsed().as_secs_f64() * 1000.0),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    /// `ready`, `degraded` (an optional dependency failed) or `not_ready`
+    pub status: &'static str,
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+impl Readiness {
+    pub fn from_checks(checks: BTreeMap<&'static str, Check>) -> Self {
+        let failed = |required: bool| checks.values().any(|c| c.status == CheckStatus::Fail && c.required == required);
+        let status = if failed(true) {
+            "not_ready"
+        } else if failed(false) {
+            "degraded"
+        } else {
+            "ready"
+        };
+        Readiness { status, checks }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.status != "not_ready"
+    }
+}
+
+/// Creates and removes a probe file in the data root.
+pub async fn data_dir_writable() -> Check {
+    let started = Instant::now();
+    let probe = paths::data_root().join(".health-probe");
+    let result = match fs::write(&probe, b"ok").await {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe).await;
+            Ok(String::new())
+        }
+        Err(e) => Err(format!("cannot write to {}: {}", paths::data_root().display(), e)),
+    };
+    Check::new(true, started, result)
+}
+
+/// Free space on the data root's disk, in MB.
+pub async fn available_mb() -> std::io::Result<u64> {
+    let root = paths::data_root().to_path_buf();
+    let bytes = tokio::task::spawn_blocking(move || fs2::available_space(root)).await.map_err(std::io::Error::other)??;
+    Ok(bytes / (1024 * 1024))
+}
+
+pub async fn free_disk(min_free_mb: u64) -> Check {
+    let started = Instant::now();
+    let result = match available_mb().await {
+        Ok(free_mb) if free_mb < min_free_mb => Err(format!("{} MB free, below the {} MB minimum", free_mb, min_free_mb)),
+        Ok(free_mb) => Ok(format!("{} MB free", free_mb)),
+        Err(e) => Err(format!("cannot read free space: {}", e)),
+    };
+    Check::new(true, started, result)
+}
+
+/// Every profile's blob index must load and its blob directory must exist.
+pub async fn storage(stores: &[(&str, &BlobStore)]) -> Check {
+    let started = Instant::now();
+    let mut blobs = 0;
+    for (profile, store) in stores {
+        match store.check().await {
+            Ok(n) => blobs += n,
+            Err(e) => return Check::new(true, started, Err(format!("profile {}: {:#}", profile, e))),
+        }
+    }
+    Check::new(true, started, Ok(format!("{} blobs", blobs)))
+}
+
+/// Influx is optional: entries are kept locally when it is down, so a failure only degrades.
+pub async fn influx(client: Option<&InfluxClient>, writes_paused: bool) -> Check {
+    let Some(client) = client else {
+        return Check { status: CheckStatus::Disabled, required: false, detail: None, latency_ms: None };
+    };
+    let started = Instant::now();
+    let result = match client.ping(INFLUX_TIMEOUT).await {
+        Ok(()) if writes_paused => Ok("reachable, but writes were paused after an earlier connection failure".to_string()),
+        Ok(()) => Ok(String::new()),
+        Err(e) => Err(e.to_string()),
+    };
+    Check::new(false, started, result)
+}
@@ -0,0 +1,248 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+
+use super::color::Region;
+use crate::config::env_flag;
+use super::VIEW_NAMES;
+
+/// Per-channel gains are clamped to this range so a mis-detected patch cannot wreck a photo.
+const MAX_GAIN: f32 = 4.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalibrationOptions {
+    pub enabled: bool,
+    /// Where the grey reference card sits in each view
+    pub patches: BTreeMap<String, Region>,
+    /// sRGB value the card is mapped to; 118 is a standard 18% grey card
+    pub target: u8,
+    /// Also scale overall brightness to the target, not just balance the channels
+    pub normalize_exposure: bool,
+    /// Largest luma standard deviation inside the patch for it to count as a card
+    pub max_patch_stddev: f64,
+}
+
+impl Default for CalibrationOptions {
+    fn default() -> Self {
+        CalibrationOptions { enabled: false, patches: BTreeMap::new(), target: 118, normalize_exposure: true, max_patch_stddev: 20.0 }
+    }
+}
+
+impl CalibrationOptions {
+    /// Overrides settings from VITAL_CALIBRATION (on/off), VITAL_CAL_PATCH (x,y,w,h for every view)
+    /// or VITAL_CAL_PATCH_<VIEW>, VITAL_CAL_TARGET, VITAL_CAL_EXPOSURE and VITAL_CAL_MAX_STDDEV.
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Some(b) = env_flag("VITAL_CALIBRATION") {
+            self.enabled = b;
+        }
+        if let Ok(v) = env::var("VITAL_CAL_PATCH") {
+            let region = Region::parse(&v).map_err(|e| anyhow!("VITAL_CAL_PATCH: {}", e))?;
+            for view in VIEW_NAMES {
+                self.patches.insert(view.to_string(), region);
+            }
+        }
+        for view in VIEW_NAMES {
+            let key = format!("VITAL_CAL_PATCH_{}", view.to_ascii_uppercase());
+            if let Ok(v) = env::var(&key) {
+                if v.trim().eq_ignore_ascii_case("off") {
+                    self.patches.remove(view);
+                } else {
+                    self.patches.insert(view.to_string(), Region::parse(&v).map_err(|e| anyhow!("{}: {}", key, e))?);
+                }
+            }
+        }
+        if let Ok(v) = env::var("VITAL_CAL_TARGET") {
+            self.target = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_CAL_TARGET: {}", v))?;
+        }
+        if let Some(b) = env_flag("VITAL_CAL_EXPOSURE") {
+            self.normalize_exposure = b;
+        }
+        if let Ok(v) = env::var("VITAL_CAL_MAX_STDDEV") {
+            self.max_patch_stddev = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_CAL_MAX_STDDEV: {}", v))?;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.enabled && self.patches.is_empty() {
+            return Err(anyhow!("calibration is enabled but no reference patch is configured (calibration.patches or VITAL_CAL_PATCH)"));
+        }
+        for (view, region) in &self.patches {
+            if !VIEW_NAMES.contains(&view.as_str()) {
+                return Err(anyhow!("calibration.patches: unknown view '{}'", view));
+            }
+            region.validate().map_err(|e| anyhow!("calibration.patches.{}: {}", view, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of calibrating one view, stored with the entry metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalibrationResult {
+    /// Whether a usable reference card was found and the correction applied
+    pub applied: bool,
+    /// Linear-light gains applied to R, G and B
+    pub gains: [f32; 3],
+    /// Mean sRGB colour measured inside the patch
+    pub patch_rgb: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    let c = c / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    let v = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    v * 255.0
+}
+
+/// Mean sRGB colour and luma standard deviation inside the patch.
+fn patch_stats(img: &RgbImage, region: Region) -> ([f32; 3], f64) {
+    let (x0, y0, x1, y1) = region.to_pixels(img.width(), img.height());
+    let (mut sum, mut luma_sum, mut luma_sq, mut n) = ([0f64; 3], 0f64, 0f64, 0f64);
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let p = img.get_pixel(x, y);
+            for c in 0..3 {
+                sum[c] += p[c] as f64;
+            }
+            let l = 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64;
+            luma_sum += l;
+            luma_sq += l * l;
+            n += 1.0;
+        }
+    }
+    let mean = [(sum[0] / n) as f32, (sum[1] / n) as f32, (sum[2] / n) as f32];
+    let luma_mean = luma_sum / n;
+    (mean, (luma_sq / n - luma_mean * luma_mean).max(0.0).sqrt())
+}
+
+/// Looks for the grey card at `region` and, if it is plausible, rescales every channel in
+/// linear light so the card comes out neutral (and at `target` when exposure is normalised).
+/// Returns the corrected image, or the original untouched when no card was detected.
+/// CPU bound; call from a blocking thread.
+pub fn normalize(img: DynamicImage, region: Region, opts: &CalibrationOptions) -> (DynamicImage, CalibrationResult) {
+    let mut rgb = img.to_rgb8();
+    let (mean, stddev) = patch_stats(&rgb, region);
+    let skipped = |note: String| CalibrationResult { applied: false, gains: [1.0; 3], patch_rgb: mean, note: Some(note) };
+
+    if stddev > opts.max_patch_stddev {
+        return (img, skipped(format!("reference patch not uniform (stddev {:.1})", stddev)));
+    }
+    if mean.iter().any(|c| *c < 10.0 || *c > 250.0) {
+        return (img, skipped("reference patch under- or over-exposed".to_string()));
+    }
+    let max = mean.iter().cloned().fold(f32::MIN, f32::max);
+    let min = mean.iter().cloned().fold(f32::MAX, f32::min);
+    if (max - min) / max > 0.35 {
+        return (img, skipped("reference patch is not grey".to_string()));
+    }
+
+    let lin = mean.map(srgb_to_linear);
+    let target = if opts.normalize_exposure {
+        srgb_to_linear(opts.target as f32)
+    } else {
+        0.2126 * lin[0] + 0.7152 * lin[1] + 0.0722 * lin[2]
+    };
+    let gains = lin.map(|c| (target / c.max(1e-6)).clamp(1.0 / MAX_GAIN, MAX_GAIN));
+
+    let luts: Vec<[u8; 256]> = gains
+        .iter()
+        .map(|g| {
+            let mut lut = [0u8; 256];
+            for (i, v) in lut.iter_mut().enumerate() {
+                *v = linear_to_srgb(srgb_to_linear(i as f32) * g).round() as u8;
+            }
+            lut
+        })
+        .collect();
+    for p in rgb.pixels_mut() {
+        for c in 0..3 {
+            p[c] = luts[c][p[c] as usize];
+        }
+    }
+    (DynamicImage::ImageRgb8(rgb), CalibrationResult { applied: true, gains, patch_rgb: mean, note: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    const PATCH: Region = Region { x: 0.0, y: 0.0, w: 0.5, h: 0.5 };
+
+    /// Card colour in the top-left quarter, `rest` elsewhere
+    fn photo(card: [u8; 3], rest: impl Fn(u32, u32) -> [u8; 3]) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(40, 40, |x, y| Rgb(if x < 20 && y < 20 { card } else { rest(x, y) })))
+    }
+
+    fn enabled() -> CalibrationOptions {
+        CalibrationOptions { enabled: true, ..CalibrationOptions::default() }
+    }
+
+    #[test]
+    fn warm_cast_is_neutralised() {
+        let img = photo([140, 118, 100], |_, _| [200, 160, 130]);
+        let (out, result) = normalize(img, PATCH, &enabled());
+        assert!(result.applied, "{:?}", result.note);
+        assert!(result.gains[0] < 1.0 && result.gains[2] > 1.0, "{:?}", result.gains);
+        let out = out.to_rgb8();
+        let card = out.get_pixel(5, 5);
+        for c in 0..3 {
+            assert!((card[c] as i32 - 118).abs() <= 1, "card {:?}", card);
+        }
+        // The rest of the photo is corrected the same way: red no longer dominates
+        let skin = out.get_pixel(30, 30);
+        assert!(skin[0] < 200 && skin[2] > 130, "skin {:?}", skin);
+    }
+
+    #[test]
+    fn balance_only_keeps_brightness() {
+        let opts = CalibrationOptions { normalize_exposure: false, ..enabled() };
+        let (out, result) = normalize(photo([90, 80, 70], |_, _| [0, 0, 0]), PATCH, &opts);
+        assert!(result.applied);
+        let card = *out.to_rgb8().get_pixel(5, 5);
+        assert!((card[0] as i32 - card[2] as i32).abs() <= 1, "card {:?}", card);
+        assert!((75..=85).contains(&card[1]), "card {:?}", card);
+    }
+
+    #[test]
+    fn zero_channel_is_left_alone() {
+        let img = photo([200, 150, 0], |_, _| [100, 100, 100]);
+        let (out, result) = normalize(img.clone(), PATCH, &enabled());
+        assert!(!result.applied);
+        assert_eq!(result.gains, [1.0; 3]);
+        assert_eq!(out.to_rgb8(), img.to_rgb8());
+    }
+
+    #[test]
+    fn missing_card_is_left_alone() {
+        // No card: the patch covers a checkerboard
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(40, 40, |x, y| if (x + y) % 2 == 0 { Rgb([20, 20, 20]) } else { Rgb([230, 230, 230]) }));
+        let (out, result) = normalize(img.clone(), PATCH, &enabled());
+        assert!(!result.applied);
+        assert!(result.note.unwrap().contains("not uniform"));
+        assert_eq!(out.to_rgb8(), img.to_rgb8());
+    }
+
+    #[test]
+    fn coloured_patch_is_not_a_card() {
+        let (_, result) = normalize(photo([200, 60, 60], |_, _| [0, 0, 0]), PATCH, &enabled());
+        assert!(!result.applied);
+        assert_eq!(result.note.as_deref(), Some("reference patch is not grey"));
+    }
+
+    #[test]
+    fn enabled_without_patches_is_invalid() {
+        assert!(enabled().validate().is_err());
+        assert!(CalibrationOptions::default().validate().is_ok());
+    }
+}
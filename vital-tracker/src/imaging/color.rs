@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Result};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+
+use super::VIEW_NAMES;
+
+/// Upper bound on pixels averaged per region; larger regions are sampled on a grid.
+const MAX_SAMPLES: u64 = 100_000;
+
+/// Rectangle within a view, as fractions (0-1) of the image width and height.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Region {
+    /// Parses "x,y,w,h" with every value a fraction of the image size.
+    pub fn parse(s: &str) -> Result<Self> {
+        let parts: Vec<f32> = s.split(',').map(|p| p.trim().parse::<f32>()).collect::<Result<_, _>>().map_err(|_| anyhow!("invalid region '{}', expected x,y,w,h", s))?;
+        let [x, y, w, h] = parts[..] else {
+            return Err(anyhow!("invalid region '{}', expected x,y,w,h", s));
+        };
+        let region = Region { x, y, w, h };
+        region.validate()?;
+        Ok(region)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let Region { x, y, w, h } = *self;
+        if ![x, y, w, h].iter().all(|v| v.is_finite()) {
+            return Err(anyhow!("region {},{},{},{} must be finite numbers", x, y, w, h));
+        }
+        if x < 0.0 || y < 0.0 || w <= 0.0 || h <= 0.0 || x + w > 1.0 || y + h > 1.0 {
+            return Err(anyhow!("region {},{},{},{} must lie within 0..1 of the image", x, y, w, h));
+        }
+        Ok(())
+    }
+
+    /// Pixel bounds (x0, y0, x1, y1) of the region in an image of the given size; never empty.
+    pub fn to_pixels(self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x0 = ((self.x * width as f32) as u32).min(width.saturating_sub(1));
+        let y0 = ((self.y * height as f32) as u32).min(height.saturating_sub(1));
+        let x1 = (((self.x + self.w) * width as f32).ceil() as u32).clamp(x0 + 1, width);
+        let y1 = (((self.y + self.h) * height as f32).ceil() as u32).clamp(y0 + 1, height);
+        (x0, y0, x1, y1)
+    }
+}
+
+/// Centre half of the frame; where the jaw and neck sit in the web UI's captures.
+const DEFAULT_REGION: Region = Region { x: 0.25, y: 0.25, w: 0.5, h: 0.5 };
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorOptions {
+    /// Views that get a colour measurement, with the region to measure
+    pub regions: BTreeMap<String, Region>,
+}
+
+impl Default for ColorOptions {
+    fn default() -> Self {
+        let regions = ["left", "right", "neck"].iter().map(|v| (v.to_string(), DEFAULT_REGION)).collect();
+        ColorOptions { regions }
+    }
+}
+
+impl ColorOptions {
+    /// Overrides regions from VITAL_ROI_<VIEW> (e.g. VITAL_ROI_NECK=0.3,0.4,0.4,0.3); "off" disables a view.
+    pub fn apply_env(&mut self) -> Result<()> {
+        for view in VIEW_NAMES {
+            let key = format!("VITAL_ROI_{}", view.to_ascii_uppercase());
+            if let Ok(v) = env::var(&key) {
+                if v.trim().eq_ignore_ascii_case("off") {
+                    self.regions.remove(view);
+                } else {
+                    let region = Region::parse(&v).map_err(|e| anyhow!("{}: {}", key, e))?;
+                    self.regions.insert(view.to_string(), region);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (view, region) in &self.regions {
+            if !VIEW_NAMES.contains(&view.as_str()) {
+                return Err(anyhow!("color.regions: unknown view '{}'", view));
+            }
+            region.validate().map_err(|e| anyhow!("color.regions.{}: {}", view, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Average colour of a view's region of interest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColorMetrics {
+    /// CIELAB lightness, 0-100
+    pub l: f64,
+    /// CIELAB green(-)/red(+) axis; the main inflammation signal
+    pub a: f64,
+    /// CIELAB blue(-)/yellow(+) axis
+    pub b: f64,
+    /// Mean of (R - G) / (R + G), an erythema-style index in -1..1
+    pub redness: f64,
+    pub region: Region,
+}
+
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// sRGB (D65) to CIELAB.
+pub fn rgb_to_lab(r: u8, g: u8, b: u8) -> (f64, f64, f64) {
+    let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f64| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// Measures the average colour inside `region`. CPU bound; call from a blocking thread.
+pub fn measure(img: &DynamicImage, region: Region) -> ColorMetrics {
+    let rgb = img.to_rgb8();
+    let (x0, y0, x1, y1) = region.to_pixels(rgb.width(), rgb.height());
+    let area = (x1 - x0) as u64 * (y1 - y0) as u64;
+    let step = ((area as f64 / MAX_SAMPLES as f64).sqrt().ceil() as u32).max(1);
+
+    let (mut sl, mut sa, mut sb, mut sr, mut n) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for y in (y0..y1).step_by(step as usize) {
+        for x in (x0..x1).step_by(step as usize) {
+            let p = rgb.get_pixel(x, y);
+            let (l, a, b) = rgb_to_lab(p[0], p[1], p[2]);
+            sl += l;
+            sa += a;
+            sb += b;
+            let (r, g) = (p[0] as f64, p[1] as f64);
+            if r + g > 0.0 {
+                sr += (r - g) / (r + g);
+            }
+            n += 1.0;
+        }
+    }
+    ColorMetrics { l: sl / n, a: sa / n, b: sb / n, redness: sr / n, region }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_lab(rgb: (u8, u8, u8), expected: (f64, f64, f64)) {
+        let (l, a, b) = rgb_to_lab(rgb.0, rgb.1, rgb.2);
+        let close = (l - expected.0).abs() < 0.2 && (a - expected.1).abs() < 0.2 && (b - expected.2).abs() < 0.2;
+        assert!(close, "{:?} -> ({:.2}, {:.2}, {:.2}), expected {:?}", rgb, l, a, b, expected);
+    }
+
+    #[test]
+    fn lab_matches_reference_values() {
+        assert_lab((0, 0, 0), (0.0, 0.0, 0.0));
+        assert_lab((255, 255, 255), (100.0, 0.0, 0.0));
+        assert_lab((128, 128, 128), (53.59, 0.0, 0.0));
+        assert_lab((255, 0, 0), (53.24, 80.09, 67.20));
+        assert_lab((0, 255, 0), (87.73, -86.18, 83.18));
+        assert_lab((0, 0, 255), (32.30, 79.19, -107.86));
+    }
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!(Region::parse("0.1, 0.2,0.3,0.4").unwrap(), Region { x: 0.1, y: 0.2, w: 0.3, h: 0.4 });
+        assert_eq!(Region::parse("0,0,1,1").unwrap(), Region { x: 0.0, y: 0.0, w: 1.0, h: 1.0 });
+        for bad in ["", "0.1,0.2,0.3", "0.1,0.2,0.3,0.4,0.5", "a,b,c,d"] {
+            assert!(Region::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rejects_regions_outside_the_image() {
+        for bad in ["-0.1,0,0.5,0.5", "0,0,0,0.5", "0,0,0.5,-1", "0.6,0,0.5,0.5", "0,0.5,0.5,0.6"] {
+            assert!(Region::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn rejects_non_finite_regions() {
+        for bad in ["NaN,0,0.5,0.5", "0,0,nan,0.5", "0,0,0.5,inf", "-inf,0,0.5,0.5"] {
+            assert!(Region::parse(bad).is_err(), "{}", bad);
+        }
+        let region = Region { x: 0.0, y: f32::NAN, w: 0.5, h: 0.5 };
+        assert!(region.validate().is_err());
+    }
+
+    #[test]
+    fn pixel_bounds_are_never_empty() {
+        assert_eq!(DEFAULT_REGION.to_pixels(100, 80), (25, 20, 75, 60));
+        let sliver = Region { x: 0.999, y: 0.999, w: 0.001, h: 0.001 };
+        assert_eq!(sliver.to_pixels(10, 10), (9, 9, 10, 10));
+    }
+}
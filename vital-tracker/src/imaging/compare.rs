@@ -0,0 +1,36 @@
+use anyhow::Result;
+use image::{DynamicImage, GenericImage, Rgb, RgbImage};
+
+use super::{compose_strip, text, StageTimings};
+
+/// One side of a comparison: the photo plus the caption lines printed under it.
+pub struct ComparisonSide {
+    pub image: RgbImage,
+    pub caption: Vec<String>,
+}
+
+/// Places two photos side by side at a common height (via `compose_strip`) with each side's
+/// caption in a band underneath. CPU bound; call from a blocking thread.
+pub fn render_comparison(a: ComparisonSide, b: ComparisonSide) -> Result<RgbImage> {
+    let (aw, ah) = a.image.dimensions();
+    let captions = [a.caption, b.caption];
+    let views = [Some(DynamicImage::ImageRgb8(a.image)), Some(DynamicImage::ImageRgb8(b.image))];
+    let mut timings = StageTimings::default();
+    let strip = compose_strip(&views, &mut timings)?;
+
+    let scale = (strip.height() / 240).max(1);
+    let line_h = text::label_height(scale);
+    let lines = captions.iter().map(|c| c.len()).max().unwrap_or(0) as u32;
+    let band = lines * line_h + scale * 4;
+
+    let mut canvas = RgbImage::from_pixel(strip.width(), strip.height() + band, Rgb([20, 20, 20]));
+    canvas.copy_from(&strip, 0, 0)?;
+    // compose_strip scales the left photo to the strip height, so the right one starts where it ends
+    let offsets = [0, (aw as f32 * strip.height() as f32 / ah as f32) as u32];
+    for (caption, x) in captions.iter().zip(offsets) {
+        for (i, line) in caption.iter().enumerate() {
+            text::draw_label(&mut canvas, x + scale * 2, strip.height() + scale * 2 + i as u32 * line_h, line, scale);
+        }
+    }
+    Ok(canvas)
+}
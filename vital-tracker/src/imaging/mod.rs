@@ -0,0 +1,178 @@
+use anyhow::Result;
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, GenericImage, RgbImage};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::crypto;
+
+pub mod calibration;
+pub mod color;
+pub mod compare;
+pub mod output;
+pub mod quality;
+pub mod text;
+pub mod timelapse;
+
+/// Order in which the captured views are laid out in the composite strip.
+pub const VIEW_NAMES: [&str; 4] = ["front", "left", "right", "neck"];
+
+/// Wall-clock time spent in each stage of the entry image pipeline.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StageTimings {
+    pub decode: Duration,
+    pub quality: Duration,
+    pub resize: Duration,
+    pub composite: Duration,
+    pub encode: Duration,
+}
+
+impl StageTimings {
+    pub fn total(&self) -> Duration {
+        self.decode + self.quality + self.resize + self.composite + self.encode
+    }
+}
+
+impl fmt::Display for StageTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "decode={}ms quality={}ms resize={}ms composite={}ms encode={}ms total={}ms",
+            self.decode.as_millis(),
+            self.quality.as_millis(),
+            self.resize.as_millis(),
+            self.composite.as_millis(),
+            self.encode.as_millis(),
+            self.total().as_millis()
+        )
+    }
+}
+
+/// Decodes an uploaded photo, guessing the format from its bytes. CPU bound; call from a blocking thread.
+pub fn decode_image(bytes: &[u8]) -> Result<DynamicImage> {
+    let img = ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?.decode()?;
+    Ok(img)
+}
+
+/// Decodes all provided views in parallel on the blocking pool, preserving slot order.
+pub async fn decode_views(photos: [Option<Vec<u8>>; 4]) -> Result<([Option<DynamicImage>; 4], Duration)> {
+    let start = Instant::now();
+    // Spawn every decode before awaiting any of them so they run concurrently
+    let handles = photos.map(|p| p.map(|bytes| tokio::task::spawn_blocking(move || decode_image(&bytes))));
+    let mut out: [Option<DynamicImage>; 4] = [None, None, None, None];
+    for (slot, handle) in out.iter_mut().zip(handles) {
+        if let Some(h) = handle {
+            *slot = Some(h.await??);
+        }
+    }
+    Ok((out, start.elapsed()))
+}
+
+/// Runs the quality measurements on every provided view, keyed by view name. CPU bound; call from a blocking thread.
+pub fn assess_views(views: &[Option<DynamicImage>]) -> BTreeMap<String, quality::PhotoQuality> {
+    VIEW_NAMES
+        .iter()
+        .zip(views)
+        .filter_map(|(name, v)| v.as_ref().map(|img| (name.to_string(), quality::analyze(img))))
+        .collect()
+}
+
+/// Colour of the configured region of interest for every view that has one. CPU bound; call from a blocking thread.
+pub fn measure_colors(views: &[Option<DynamicImage>], opts: &color::ColorOptions) -> BTreeMap<String, color::ColorMetrics> {
+    VIEW_NAMES
+        .iter()
+        .zip(views)
+        .filter_map(|(name, v)| {
+            let region = opts.regions.get(*name)?;
+            v.as_ref().map(|img| (name.to_string(), color::measure(img, *region)))
+        })
+        .collect()
+}
+
+/// Applies grey-card white balance to every view that has a reference patch configured.
+/// CPU bound; call from a blocking thread.
+pub fn calibrate_views(views: [Option<DynamicImage>; 4], opts: &calibration::CalibrationOptions) -> ([Option<DynamicImage>; 4], BTreeMap<String, calibration::CalibrationResult>) {
+    let mut results = BTreeMap::new();
+    let mut out: [Option<DynamicImage>; 4] = [None, None, None, None];
+    for ((slot, view), name) in out.iter_mut().zip(views).zip(VIEW_NAMES) {
+        *slot = match (view, opts.patches.get(name)) {
+            (Some(img), Some(region)) if opts.enabled => {
+                let (img, result) = calibration::normalize(img, *region, opts);
+                results.insert(name.to_string(), result);
+                Some(img)
+            }
+            (view, _) => view,
+        };
+    }
+    (out, results)
+}
+
+fn blank_image(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, image::Rgb([220, 220, 220])))
+}
+
+/// Scales every view to a common height and lays them out left to right.
+/// Missing views are replaced with a grey 4:3 placeholder. CPU bound; call from a blocking thread.
+pub fn compose_strip(views: &[Option<DynamicImage>], timings: &mut StageTimings) -> Result<RgbImage> {
+    if views.iter().all(|v| v.is_none()) {
+        return Err(anyhow::anyhow!("no images provided"));
+    }
+
+    let start = Instant::now();
+    let height = views
+        .iter()
+        .filter_map(|i| i.as_ref().map(|img| img.height()))
+        .max()
+        .unwrap_or(480);
+
+    let mut resized: Vec<RgbImage> = Vec::with_capacity(views.len());
+    for img_opt in views {
+        if let Some(img) = img_opt {
+            let w = ((img.width() as f32) * (height as f32) / (img.height() as f32)) as u32;
+            let r = img.resize_exact(w, height, image::imageops::FilterType::Triangle);
+            resized.push(r.to_rgb8());
+        } else {
+            // Use a 4:3 blank placeholder if missing
+            resized.push(blank_image(height * 4 / 3, height).to_rgb8());
+        }
+    }
+    timings.resize = start.elapsed();
+
+    let start = Instant::now();
+    let total_width: u32 = resized.iter().map(|i| i.width()).sum();
+    let mut imgbuf = RgbImage::new(total_width, height);
+    let mut x = 0u32;
+    for img in &resized {
+        imgbuf.copy_from(img, x, 0)?;
+        x += img.width();
+    }
+    timings.composite = start.elapsed();
+
+    Ok(imgbuf)
+}
+
+/// Recovers a single view from a legacy composite that predates per-view storage.
+/// Only works for strips made of four 4:3 slots (what the web UI captures); returns None
+/// for other layouts and for slots that hold the grey "missing view" placeholder.
+pub fn crop_view_from_strip(strip: &RgbImage, index: usize) -> Option<RgbImage> {
+    let slot_w = strip.height() * 4 / 3;
+    if index >= VIEW_NAMES.len() || slot_w == 0 || strip.width().abs_diff(slot_w * 4) > 4 {
+        return None;
+    }
+    let slot_w = strip.width() / 4;
+    let view = image::imageops::crop_imm(strip, slot_w * index as u32, 0, slot_w, strip.height()).to_image();
+    let placeholder = image::Rgb([220, 220, 220]);
+    if view.pixels().all(|p| *p == placeholder) {
+        return None;
+    }
+    Some(view)
+}
+
+/// Loads one view from disk: either a per-view photo, or (when `legacy`) the composite it
+/// has to be cut out of. `None` if the file is missing, undecodable or lacks that view.
+pub fn load_view(file: &std::path::Path, legacy: bool, view_index: usize) -> Option<RgbImage> {
+    let bytes = crypto::read_blocking(file).ok()?;
+    let img = decode_image(&bytes).ok()?.to_rgb8();
+    if legacy { crop_view_from_strip(&img, view_index) } else { Some(img) }
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Result};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{ColorType, ImageEncoder, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::env;
+
+/// File extensions recognised as stored entry photos, whatever format they were written in.
+pub const PHOTO_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// Encoding used for stored composites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Png,
+    /// Lossless VP8L; the image crate does not ship a pure-Rust lossy WebP encoder.
+    WebP,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "png" => Some(OutputFormat::Png),
+            "webp" => Some(OutputFormat::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::WebP => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    pub format: OutputFormat,
+    /// 1-100, only used for JPEG output
+    pub jpeg_quality: u8,
+    /// Longest side of the stored image in pixels; larger composites are scaled down
+    pub max_dimension: Option<u32>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions { format: OutputFormat::Jpeg, jpeg_quality: 85, max_dimension: None }
+    }
+}
+
+impl ImageOptions {
+    /// Overrides settings from VITAL_IMAGE_FORMAT, VITAL_JPEG_QUALITY and VITAL_IMAGE_MAX_DIM.
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Ok(v) = env::var("VITAL_IMAGE_FORMAT") {
+            self.format = OutputFormat::parse(&v).ok_or_else(|| anyhow!("unsupported VITAL_IMAGE_FORMAT: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_JPEG_QUALITY") {
+            self.jpeg_quality = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_JPEG_QUALITY: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_IMAGE_MAX_DIM") {
+            let d: u32 = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_IMAGE_MAX_DIM: {}", v))?;
+            self.max_dimension = if d == 0 { None } else { Some(d) };
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=100).contains(&self.jpeg_quality) {
+            return Err(anyhow!("image.jpeg_quality must be between 1 and 100, got {}", self.jpeg_quality));
+        }
+        if self.max_dimension == Some(0) {
+            return Err(anyhow!("image.max_dimension must be positive"));
+        }
+        Ok(())
+    }
+}
+
+/// Scales `img` down so its longest side is at most `max_dimension`, preserving aspect ratio.
+pub fn fit_within(img: RgbImage, max_dimension: Option<u32>) -> RgbImage {
+    let max = match max_dimension {
+        Some(m) if img.width() > m || img.height() > m => m,
+        _ => return img,
+    };
+    let scale = max as f32 / img.width().max(img.height()) as f32;
+    let w = ((img.width() as f32 * scale).round() as u32).max(1);
+    let h = ((img.height() as f32 * scale).round() as u32).max(1);
+    image::imageops::resize(&img, w, h, image::imageops::FilterType::Triangle)
+}
+
+/// Encodes `img` in the configured format. CPU bound; call from a blocking thread.
+pub fn encode(img: &RgbImage, opts: &ImageOptions) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let (w, h) = img.dimensions();
+    match opts.format {
+        OutputFormat::Jpeg => JpegEncoder::new_with_quality(&mut buf, opts.jpeg_quality).write_image(img.as_raw(), w, h, ColorType::Rgb8)?,
+        OutputFormat::Png => PngEncoder::new(&mut buf).write_image(img.as_raw(), w, h, ColorType::Rgb8)?,
+        OutputFormat::WebP => WebPEncoder::new_lossless(&mut buf).write_image(img.as_raw(), w, h, ColorType::Rgb8)?,
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_parse_case_insensitively() {
+        assert_eq!(OutputFormat::parse("jpg"), Some(OutputFormat::Jpeg));
+        assert_eq!(OutputFormat::parse(" JPEG "), Some(OutputFormat::Jpeg));
+        assert_eq!(OutputFormat::parse("Png"), Some(OutputFormat::Png));
+        assert_eq!(OutputFormat::parse("webp"), Some(OutputFormat::WebP));
+        assert_eq!(OutputFormat::parse("gif"), None);
+        assert_eq!(OutputFormat::parse(""), None);
+    }
+
+    #[test]
+    fn downscale_keeps_aspect_ratio() {
+        let img = fit_within(RgbImage::new(400, 200), Some(100));
+        assert_eq!(img.dimensions(), (100, 50));
+        let img = fit_within(RgbImage::new(90, 300), Some(150));
+        assert_eq!(img.dimensions(), (45, 150));
+    }
+
+    #[test]
+    fn small_images_are_not_upscaled() {
+        assert_eq!(fit_within(RgbImage::new(80, 60), Some(100)).dimensions(), (80, 60));
+        assert_eq!(fit_within(RgbImage::new(100, 100), Some(100)).dimensions(), (100, 100));
+        assert_eq!(fit_within(RgbImage::new(4000, 3000), None).dimensions(), (4000, 3000));
+    }
+
+    #[test]
+    fn thin_images_keep_at_least_one_pixel() {
+        assert_eq!(fit_within(RgbImage::new(1000, 1), Some(10)).dimensions(), (10, 1));
+    }
+
+    #[test]
+    fn encodes_each_format() {
+        let img = RgbImage::from_fn(16, 8, |x, y| image::Rgb([(x * 16) as u8, (y * 32) as u8, 128]));
+        for format in [OutputFormat::Jpeg, OutputFormat::Png, OutputFormat::WebP] {
+            let opts = ImageOptions { format, ..ImageOptions::default() };
+            let bytes = encode(&img, &opts).unwrap();
+            let decoded = image::load_from_memory(&bytes).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (16, 8), "{:?}", format);
+            let expected = match format {
+                OutputFormat::Jpeg => image::ImageFormat::Jpeg,
+                OutputFormat::Png => image::ImageFormat::Png,
+                OutputFormat::WebP => image::ImageFormat::WebP,
+            };
+            assert_eq!(image::guess_format(&bytes).unwrap(), expected);
+        }
+    }
+}
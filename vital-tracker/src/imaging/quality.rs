@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+
+/// Width photos are normalised to before measuring, so thresholds do not depend on camera resolution.
+const ANALYSIS_WIDTH: u32 = 320;
+
+/// Per-view measurements stored with the entry metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhotoQuality {
+    /// Variance of the Laplacian; low values mean a blurry frame
+    pub sharpness: f64,
+    /// Mean luma, 0-255
+    pub brightness: f64,
+    /// 64-bit DCT perceptual hash as 16 hex digits
+    pub phash: String,
+}
+
+/// What to do when a photo fails the thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityMode {
+    /// Measure and store only
+    Off,
+    /// Store the entry and report the problems in the /entry response
+    Warn,
+    /// Refuse the entry
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityOptions {
+    pub mode: QualityMode,
+    pub min_sharpness: f64,
+    pub min_brightness: f64,
+    /// Views whose hashes differ in at most this many bits are treated as the same frame
+    pub duplicate_distance: u32,
+}
+
+impl Default for QualityOptions {
+    fn default() -> Self {
+        QualityOptions { mode: QualityMode::Warn, min_sharpness: 20.0, min_brightness: 40.0, duplicate_distance: 4 }
+    }
+}
+
+impl QualityOptions {
+    /// Overrides settings from VITAL_QC_MODE (off|warn|reject), VITAL_QC_MIN_SHARPNESS,
+    /// VITAL_QC_MIN_BRIGHTNESS and VITAL_QC_DUPLICATE_DISTANCE.
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Ok(v) = env::var("VITAL_QC_MODE") {
+            self.mode = match v.trim().to_ascii_lowercase().as_str() {
+                "off" => QualityMode::Off,
+                "warn" => QualityMode::Warn,
+                "reject" => QualityMode::Reject,
+                _ => return Err(anyhow!("unsupported VITAL_QC_MODE: {}", v)),
+            };
+        }
+        if let Ok(v) = env::var("VITAL_QC_MIN_SHARPNESS") {
+            self.min_sharpness = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_QC_MIN_SHARPNESS: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_QC_MIN_BRIGHTNESS") {
+            self.min_brightness = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_QC_MIN_BRIGHTNESS: {}", v))?;
+        }
+        if let Ok(v) = env::var("VITAL_QC_DUPLICATE_DISTANCE") {
+            self.duplicate_distance = v.trim().parse().map_err(|_| anyhow!("invalid VITAL_QC_DUPLICATE_DISTANCE: {}", v))?;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.duplicate_distance > 64 {
+            return Err(anyhow!("quality.duplicate_distance must be at most 64, got {}", self.duplicate_distance));
+        }
+        Ok(())
+    }
+}
+
+fn normalized_gray(img: &DynamicImage) -> GrayImage {
+    let gray = img.to_luma8();
+    if gray.width() <= ANALYSIS_WIDTH {
+        return gray;
+    }
+    let h = ((gray.height() as f32 * ANALYSIS_WIDTH as f32 / gray.width() as f32).round() as u32).max(1);
+    image::imageops::resize(&gray, ANALYSIS_WIDTH, h, image::imageops::FilterType::Triangle)
+}
+
+fn laplacian_variance(gray: &GrayImage) -> f64 {
+    let (w, h) = gray.dimensions();
+    if w < 3 || h < 3 {
+        return 0.0;
+    }
+    let px = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    let mut n = 0.0;
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let v = px(x - 1, y) + px(x + 1, y) + px(x, y - 1) + px(x, y + 1) - 4.0 * px(x, y);
+            sum += v;
+            sum_sq += v * v;
+            n += 1.0;
+        }
+    }
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
+
+fn mean_brightness(gray: &GrayImage) -> f64 {
+    let n = (gray.width() as f64 * gray.height() as f64).max(1.0);
+    gray.pixels().map(|p| p[0] as f64).sum::<f64>() / n
+}
+
+/// Classic pHash: 32x32 greyscale, 2D DCT, low-frequency 8x8 block compared against its median.
+fn perceptual_hash(img: &DynamicImage) -> String {
+    const N: usize = 32;
+    let small = image::imageops::resize(&img.to_luma8(), N as u32, N as u32, image::imageops::FilterType::Triangle);
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+    let cos: Vec<f64> = (0..8 * N)
+        .map(|i| {
+            let (u, x) = (i / N, i % N);
+            (std::f64::consts::PI * u as f64 * (2 * x + 1) as f64 / (2 * N) as f64).cos()
+        })
+        .collect();
+    let mut coeffs = [0.0f64; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut acc = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    acc += pixels[y * N + x] * cos[u * N + x] * cos[v * N + y];
+                }
+            }
+            coeffs[v * 8 + u] = acc;
+        }
+    }
+    // The DC term only reflects overall brightness; leave it out of the median
+    let mut sorted: Vec<f64> = coeffs[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    let bits = coeffs.iter().enumerate().fold(0u64, |acc, (i, c)| if *c > median { acc | (1 << i) } else { acc });
+    format!("{:016x}", bits)
+}
+
+/// Measures one decoded view. CPU bound; call from a blocking thread.
+pub fn analyze(img: &DynamicImage) -> PhotoQuality {
+    let gray = normalized_gray(img);
+    PhotoQuality { sharpness: laplacian_variance(&gray), brightness: mean_brightness(&gray), phash: perceptual_hash(img) }
+}
+
+/// Number of differing bits between two hashes produced by `analyze`.
+pub fn hash_distance(a: &str, b: &str) -> Option<u32> {
+    let a = u64::from_str_radix(a, 16).ok()?;
+    let b = u64::from_str_radix(b, 16).ok()?;
+    Some((a ^ b).count_ones())
+}
+
+/// Human-readable problems with an entry's views: blur, darkness and views that are the same frame.
+pub fn find_issues(views: &BTreeMap<String, PhotoQuality>, opts: &QualityOptions) -> Vec<String> {
+    let mut issues = Vec::new();
+    for (name, q) in views {
+        if q.sharpness < opts.min_sharpness {
+            issues.push(format!("{} photo looks blurry (sharpness {:.1} < {:.1})", name, q.sharpness, opts.min_sharpness));
+        }
+        if q.brightness < opts.min_brightness {
+            issues.push(format!("{} photo is too dark (brightness {:.1} < {:.1})", name, q.brightness, opts.min_brightness));
+        }
+    }
+    let named: Vec<(&String, &PhotoQuality)> = views.iter().collect();
+    for (i, (a, qa)) in named.iter().enumerate() {
+        for (b, qb) in &named[i + 1..] {
+            if hash_distance(&qa.phash, &qb.phash).is_some_and(|d| d <= opts.duplicate_distance) {
+                issues.push(format!("{} and {} photos look like the same frame", a, b));
+            }
+        }
+    }
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, RgbImage};
+
+    fn gray(w: u32, h: u32, f: impl Fn(u32, u32) -> u8) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| Luma([f(x, y)]))
+    }
+
+    /// Deterministic pseudo-random noise
+    fn noise(x: u32, y: u32) -> u8 {
+        let mut v = x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663);
+        v ^= v >> 13;
+        v = v.wrapping_mul(0x5bd1_e995);
+        (v >> 24) as u8
+    }
+
+    /// A bright disc off-centre on a dark, slightly graded background
+    fn scene(dx: i32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(128, 128, |x, y| {
+            let d = ((x as f64 - 40.0 - dx as f64).powi(2) + (y as f64 - 70.0).powi(2)).sqrt();
+            let v = if d < 30.0 { 220 } else { 30 + (x / 8) as u8 };
+            image::Rgb([v, v, v])
+        }))
+    }
+
+    #[test]
+    fn flat_image_has_no_sharpness() {
+        assert_eq!(laplacian_variance(&gray(64, 64, |_, _| 128)), 0.0);
+    }
+
+    #[test]
+    fn noisy_image_is_sharper_than_smooth_one() {
+        let smooth = laplacian_variance(&gray(64, 64, |x, _| (x * 4) as u8));
+        let noisy = laplacian_variance(&gray(64, 64, noise));
+        assert!(smooth < 1.0, "smooth gradient: {}", smooth);
+        assert!(noisy > 1000.0, "noise: {}", noisy);
+    }
+
+    #[test]
+    fn tiny_images_measure_zero_sharpness() {
+        assert_eq!(laplacian_variance(&gray(2, 2, noise)), 0.0);
+    }
+
+    #[test]
+    fn brightness_is_mean_luma() {
+        assert_eq!(mean_brightness(&gray(10, 10, |_, _| 200)), 200.0);
+        assert_eq!(mean_brightness(&gray(10, 10, |x, _| if x < 5 { 0 } else { 100 })), 50.0);
+    }
+
+    #[test]
+    fn identical_images_hash_the_same() {
+        let a = perceptual_hash(&scene(0));
+        assert_eq!(a.len(), 16);
+        assert_eq!(hash_distance(&a, &perceptual_hash(&scene(0))), Some(0));
+    }
+
+    #[test]
+    fn slightly_shifted_image_stays_close() {
+        let d = hash_distance(&perceptual_hash(&scene(0)), &perceptual_hash(&scene(2))).unwrap();
+        assert!(d <= QualityOptions::default().duplicate_distance, "distance {}", d);
+    }
+
+    #[test]
+    fn different_image_is_far_apart() {
+        let inverted = {
+            let mut img = scene(0);
+            img.invert();
+            img
+        };
+        let d = hash_distance(&perceptual_hash(&scene(0)), &perceptual_hash(&inverted)).unwrap();
+        assert!(d > 16, "distance {}", d);
+    }
+
+    #[test]
+    fn hash_distance_rejects_garbage() {
+        assert_eq!(hash_distance("zz", "00"), None);
+    }
+
+    #[test]
+    fn issues_report_blur_darkness_and_duplicates() {
+        let q = |sharpness, brightness, phash: &str| PhotoQuality { sharpness, brightness, phash: phash.to_string() };
+        let views = BTreeMap::from([
+            ("front".to_string(), q(5.0, 100.0, "00000000000000ff")),
+            ("left".to_string(), q(50.0, 10.0, "00000000000000fe")),
+            ("neck".to_string(), q(50.0, 100.0, "ffffffffffffff00")),
+        ]);
+        let issues = find_issues(&views, &QualityOptions::default());
+        assert_eq!(issues.len(), 3, "{:?}", issues);
+        assert!(issues[0].starts_with("front photo looks blurry"));
+        assert!(issues[1].starts_with("left photo is too dark"));
+        assert_eq!(issues[2], "front and left photos look like the same frame");
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, GenericImage, Rgb, RgbImage};
+use std::time::Duration;
+
+use super::text;
+
+/// Frames beyond this are dropped by even subsampling to keep memory and encode time bounded.
+pub const MAX_FRAMES: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct TimelapseOptions {
+    pub fps: f32,
+    /// Frame height in pixels; width follows the 4:3 capture aspect
+    pub height: u32,
+    pub overlay_dates: bool,
+}
+
+impl Default for TimelapseOptions {
+    fn default() -> Self {
+        TimelapseOptions { fps: 2.0, height: 360, overlay_dates: false }
+    }
+}
+
+/// One source photo for the time-lapse together with the date label drawn on it.
+pub struct TimelapseFrame {
+    pub image: RgbImage,
+    pub label: String,
+}
+
+/// Scales `img` to fit a black `width`x`height` canvas, centred.
+fn letterbox(img: &RgbImage, width: u32, height: u32) -> Result<RgbImage> {
+    let scale = (width as f32 / img.width() as f32).min(height as f32 / img.height() as f32);
+    let w = ((img.width() as f32 * scale).round() as u32).clamp(1, width);
+    let h = ((img.height() as f32 * scale).round() as u32).clamp(1, height);
+    let scaled = image::imageops::resize(img, w, h, image::imageops::FilterType::Triangle);
+    let mut canvas = RgbImage::from_pixel(width, height, Rgb([0, 0, 0]));
+    canvas.copy_from(&scaled, (width - w) / 2, (height - h) / 2)?;
+    Ok(canvas)
+}
+
+/// Keeps at most `max` items, spread evenly over the input.
+pub fn subsample<T>(items: Vec<T>, max: usize) -> Vec<T> {
+    if items.len() <= max || max == 0 {
+        return items;
+    }
+    let step = items.len() as f64 / max as f64;
+    let keep: Vec<usize> = (0..max).map(|i| (i as f64 * step) as usize).collect();
+    items.into_iter().enumerate().filter(|(i, _)| keep.binary_search(i).is_ok()).map(|(_, t)| t).collect()
+}
+
+/// Renders the frames as an endlessly looping animated GIF, or `None` when `frames` yields
+/// nothing. Frames are letterboxed and encoded one at a time, so pass a lazy iterator to keep
+/// only one full-size photo in memory. CPU bound; call from a blocking thread.
+pub fn render_gif(frames: impl IntoIterator<Item = TimelapseFrame>, opts: &TimelapseOptions) -> Result<Option<Vec<u8>>> {
+    let height = opts.height.max(16);
+    let width = height * 4 / 3;
+    // clamp passes NaN through, and a NaN duration panics
+    let fps = if opts.fps.is_finite() { opts.fps.clamp(0.1, 30.0) } else { TimelapseOptions::default().fps };
+    let delay = Delay::from_saturating_duration(Duration::from_secs_f32(1.0 / fps));
+    let scale = (height / 180).max(1);
+
+    let mut out = Vec::new();
+    let mut count = 0;
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut out, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        for f in frames {
+            let mut canvas = letterbox(&f.image, width, height)?;
+            drop(f.image);
+            if opts.overlay_dates {
+                let y = height.saturating_sub(text::label_height(scale) + scale * 2);
+                text::draw_label(&mut canvas, scale * 2, y, &f.label, scale);
+            }
+            let rgba = image::DynamicImage::ImageRgb8(canvas).to_rgba8();
+            encoder.encode_frame(Frame::from_parts(rgba, 0, 0, delay))?;
+            count += 1;
+        }
+    }
+    Ok(if count == 0 { None } else { Some(out) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsample_keeps_short_inputs() {
+        assert_eq!(subsample(vec![1, 2, 3], 5), vec![1, 2, 3]);
+        assert_eq!(subsample(vec![1, 2, 3], 3), vec![1, 2, 3]);
+        assert_eq!(subsample(vec![1, 2, 3], 0), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn subsample_spreads_evenly() {
+        assert_eq!(subsample((0..10).collect(), 5), vec![0, 2, 4, 6, 8]);
+        assert_eq!(subsample((0..10).collect(), 3), vec![0, 3, 6]);
+        let kept = subsample((0..2000).collect::<Vec<_>>(), MAX_FRAMES);
+        assert_eq!(kept.len(), MAX_FRAMES);
+        assert_eq!(kept[0], 0);
+        assert!(*kept.last().unwrap() >= 1990);
+    }
+
+    #[test]
+    fn letterbox_centres_wide_image() {
+        let img = RgbImage::from_pixel(200, 50, Rgb([255, 255, 255]));
+        let out = letterbox(&img, 80, 60).unwrap();
+        assert_eq!(out.dimensions(), (80, 60));
+        // 200x50 scales to 80x20, leaving 20 black rows above and below
+        assert_eq!(out.get_pixel(40, 5), &Rgb([0, 0, 0]));
+        assert_eq!(out.get_pixel(40, 30), &Rgb([255, 255, 255]));
+        assert_eq!(out.get_pixel(40, 55), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn letterbox_centres_tall_image() {
+        let img = RgbImage::from_pixel(30, 120, Rgb([255, 255, 255]));
+        let out = letterbox(&img, 80, 60).unwrap();
+        assert_eq!(out.dimensions(), (80, 60));
+        // 30x120 scales to 15x60, leaving black bars left and right
+        assert_eq!(out.get_pixel(5, 30), &Rgb([0, 0, 0]));
+        assert_eq!(out.get_pixel(40, 30), &Rgb([255, 255, 255]));
+        assert_eq!(out.get_pixel(75, 30), &Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn letterbox_handles_degenerate_sizes() {
+        let out = letterbox(&RgbImage::new(1000, 1), 64, 48).unwrap();
+        assert_eq!(out.dimensions(), (64, 48));
+    }
+
+    #[test]
+    fn renders_frames_at_requested_height() {
+        let frames = (0..3).map(|i| TimelapseFrame { image: RgbImage::from_pixel(400, 300, Rgb([i * 80, 0, 0])), label: format!("day {}", i) });
+        let opts = TimelapseOptions { fps: f32::NAN, height: 48, overlay_dates: true };
+        let gif = render_gif(frames, &opts).unwrap().unwrap();
+        let decoded = image::load_from_memory(&gif).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+    }
+
+    #[test]
+    fn no_frames_render_nothing() {
+        assert!(render_gif(Vec::new(), &TimelapseOptions::default()).unwrap().is_none());
+    }
+}
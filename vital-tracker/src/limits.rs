@@ -0,0 +1,243 @@
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::LimitsConfig;
+
+/// Client buckets kept before idle ones are dropped.
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// Token bucket holding up to a minute's worth of requests and refilling continuously.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(per_minute: u32, now: Instant) -> Self {
+        Bucket { tokens: per_minute as f64, updated: now }
+    }
+
+    fn refill(&mut self, per_minute: u32, now: Instant) {
+        let rate = per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() * rate).min(per_minute as f64);
+        self.updated = now;
+    }
+
+    /// How long until a request would be allowed; zero when one is.
+    fn wait(&self, per_minute: u32) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) * 60.0 / per_minute as f64)
+        }
+    }
+
+    /// Refills, then spends one request or returns how long until one is available.
+    fn take(&mut self, per_minute: u32, now: Instant) -> Result<(), Duration> {
+        self.refill(per_minute, now);
+        let wait = self.wait(per_minute);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+struct Buckets {
+    global: Bucket,
+    clients: HashMap<IpAddr, Bucket>,
+}
+
+/// Per-client and global limits on changing requests. The global bucket only counts
+/// authenticated requests, so anonymous clients cannot starve everyone else.
+pub struct RateLimiter {
+    client_per_minute: u32,
+    global_per_minute: u32,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(cfg: &LimitsConfig) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            client_per_minute: cfg.client_writes_per_minute,
+            global_per_minute: cfg.global_writes_per_minute,
+            buckets: Mutex::new(Buckets { global: Bucket::full(cfg.global_writes_per_minute, now), clients: HashMap::new() }),
+        }
+    }
+
+    /// Takes one request from the sender's bucket, or says when to retry.
+    fn take_client(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.client_per_minute == 0 {
+            return Ok(());
+        }
+        let per_minute = self.client_per_minute;
+        let mut b = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if b.clients.len() > MAX_TRACKED_CLIENTS {
+            b.clients.retain(|_, c| {
+                c.refill(per_minute, now);
+                c.tokens < per_minute as f64
+            });
+        }
+        let c = b.clients.entry(client).or_insert_with(|| Bucket::full(per_minute, now));
+        c.take(per_minute, now)
+    }
+
+    /// Takes one request from the bucket shared by every client, or says when to retry.
+    fn take_global(&self, now: Instant) -> Result<(), Duration> {
+        if self.global_per_minute == 0 {
+            return Ok(());
+        }
+        let mut b = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        b.global.take(self.global_per_minute, now)
+    }
+}
+
+fn is_write(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn too_many(which: &'static str, wait: Duration, client: Option<IpAddr>) -> Response {
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    tracing::warn!(client = ?client, limit = which, retry_after, "rate limit hit");
+    let msg = format!("too many changes ({} limit); retry in {} s", which, retry_after);
+    (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], msg).into_response()
+}
+
+/// Answers changing requests over the per-client rate with 429 and `Retry-After`. Runs
+/// before authentication, so it is the only limit anonymous clients can use up.
+pub async fn limit_client_writes<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if !is_write(req.method()) {
+        return next.run(req).await;
+    }
+    match limiter.take_client(addr.ip(), Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => too_many("client", wait, Some(addr.ip())),
+    }
+}
+
+/// Answers changing requests over the global rate with 429 and `Retry-After`. Installed
+/// behind authentication, so only accepted requests draw on the shared budget.
+pub async fn limit_global_writes<B>(State(limiter): State<Arc<RateLimiter>>, req: Request<B>, next: Next<B>) -> Response {
+    if !is_write(req.method()) {
+        return next.run(req).await;
+    }
+    match limiter.take_global(Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => too_many("global", wait, None),
+    }
+}
+
+fn declared_too_large(headers: &HeaderMap, max_bytes: u64) -> bool {
+    let declared = headers.get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    declared.is_some_and(|n| n > max_bytes)
+}
+
+/// Refuses bodies declared larger than `max_bytes` before reading them. Chunked uploads
+/// without a length are cut off by the extractors' `DefaultBodyLimit` instead.
+pub async fn limit_body<B>(State(max_bytes): State<u64>, req: Request<B>, next: Next<B>) -> Response {
+    if declared_too_large(req.headers(), max_bytes) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, format!("request body larger than the {} MB limit", max_bytes / (1024 * 1024))).into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(client: u32, global: u32) -> RateLimiter {
+        RateLimiter::new(&LimitsConfig { client_writes_per_minute: client, global_writes_per_minute: global, ..Default::default() })
+    }
+
+    #[test]
+    fn bucket_starts_full_and_empties() {
+        let now = Instant::now();
+        let mut b = Bucket::full(3, now);
+        for _ in 0..3 {
+            assert_eq!(b.take(3, now), Ok(()));
+        }
+        assert_eq!(b.take(3, now), Err(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn bucket_refills_at_the_per_minute_rate() {
+        let now = Instant::now();
+        let mut b = Bucket::full(60, now);
+        b.tokens = 0.0;
+        b.refill(60, now + Duration::from_millis(2500));
+        assert!((b.tokens - 2.5).abs() < 1e-9);
+        assert_eq!(b.wait(60), Duration::ZERO);
+        // Partly refilled: only the missing fraction is waited for
+        b.tokens = 0.25;
+        assert_eq!(b.wait(60), Duration::from_millis(750));
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_a_minute() {
+        let now = Instant::now();
+        let mut b = Bucket::full(10, now);
+        b.refill(10, now + Duration::from_secs(3600));
+        assert_eq!(b.tokens, 10.0);
+    }
+
+    #[test]
+    fn clients_have_separate_buckets() {
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let l = limiter(1, 0);
+        let now = Instant::now();
+        assert!(l.take_client(a, now).is_ok());
+        assert!(l.take_client(a, now).is_err());
+        assert!(l.take_client(b, now).is_ok());
+        assert!(l.take_client(a, now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn zero_disables_a_limit() {
+        let l = limiter(0, 0);
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert!(l.take_client("10.0.0.1".parse().unwrap(), now).is_ok());
+            assert!(l.take_global(now).is_ok());
+        }
+    }
+
+    #[test]
+    fn global_bucket_is_shared() {
+        let l = limiter(0, 2);
+        let now = Instant::now();
+        assert!(l.take_global(now).is_ok());
+        assert!(l.take_global(now).is_ok());
+        assert_eq!(l.take_global(now), Err(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        let res = too_many("client", Duration::from_millis(1500), None);
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()[header::RETRY_AFTER], "2");
+        let res = too_many("global", Duration::from_millis(10), None);
+        assert_eq!(res.headers()[header::RETRY_AFTER], "1");
+    }
+
+    #[test]
+    fn content_length_over_the_limit_is_refused() {
+        let headers = |len: &str| HeaderMap::from_iter([(header::CONTENT_LENGTH, len.parse().unwrap())]);
+        assert!(!declared_too_large(&HeaderMap::new(), 10));
+        assert!(!declared_too_large(&headers("10"), 10));
+        assert!(declared_too_large(&headers("11"), 10));
+        assert!(!declared_too_large(&headers("not a number"), 10));
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Result};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::io::IsTerminal;
+use std::time::Instant;
+use tracing::Instrument;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::field::MakeExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::{LogFormat, LogRotation, LoggingConfig};
+use crate::paths;
+
+/// Installs the global subscriber: human-readable or JSON lines on stderr, plus a rotating
+/// file under `<data_root>/logs` when enabled. Keep the returned guard alive until exit so
+/// buffered file output is flushed.
+pub fn init(cfg: &LoggingConfig) -> Result<Option<WorkerGuard>> {
+    let filter = EnvFilter::try_new(&cfg.level).map_err(|e| anyhow!("logging.level '{}': {}", cfg.level, e))?;
+    let json = cfg.format == LogFormat::Json;
+    let console = if json {
+        tracing_subscriber::fmt::layer().json().with_current_span(true).with_writer(std::io::stderr).boxed()
+    } else {
+        tracing_subscriber::fmt::layer().with_ansi(std::io::stderr().is_terminal()).with_writer(std::io::stderr).boxed()
+    };
+
+    let (file, guard) = if cfg.file {
+        let rotation = match cfg.rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        std::fs::create_dir_all(paths::logs_dir())?;
+        let appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix("vital-tracker")
+            .filename_suffix("log")
+            .max_log_files(cfg.max_files)
+            .build(paths::logs_dir())
+            .map_err(|e| anyhow!("opening log directory {}: {}", paths::logs_dir().display(), e))?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+        let layer = if json {
+            tracing_subscriber::fmt::layer().json().with_current_span(true).with_writer(writer).boxed()
+        } else {
+            // A field formatter of its own type: span fields are cached per formatter type, and
+            // the console's copy may contain colour codes
+            let fields = tracing_subscriber::fmt::format::debug_fn(|w, field, value| match field.name() {
+                "message" => write!(w, "{:?}", value),
+                name => write!(w, "{}={:?}", name, value),
+            })
+            .delimited(" ");
+            tracing_subscriber::fmt::layer().with_ansi(false).fmt_fields(fields).with_writer(writer).boxed()
+        };
+        (Some(layer), Some(guard))
+    } else {
+        (None, None)
+    };
+
+    tracing_subscriber::registry().with(filter).with(console).with(file).try_init().map_err(|e| anyhow!("installing logger: {}", e))?;
+    Ok(guard)
+}
+
+/// Set as a response extension by handlers that create or touch an entry, so the request
+/// log line can name it.
+#[derive(Clone)]
+pub struct EntryId(pub String);
+
+/// Wraps every request in a span carrying method and path, and logs one line with status,
+/// latency and (when the handler set one) the entry id once the response is ready.
+pub async fn trace_requests<B>(req: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let span = tracing::info_span!("request", method = %req.method(), path = %req.uri().path());
+    let response = next.run(req).instrument(span.clone()).await;
+    let status = response.status().as_u16();
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let entry_id = response.extensions().get::<EntryId>().map(|id| id.0.as_str());
+    let _enter = span.enter();
+    if response.status().is_server_error() {
+        tracing::error!(status, latency_ms, entry_id, "request failed");
+    } else {
+        tracing::info!(status, latency_ms, entry_id, "request finished");
+    }
+    response
+}
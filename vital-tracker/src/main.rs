@@ -1,15 +1,122 @@
+mod assets;
+mod audit;
+mod auth;
+mod blobstore;
+mod bp;
+mod cli;
+mod commands;
+mod config;
+mod crypto;
 mod db;
+mod entries;
+mod health;
+mod imaging;
+mod limits;
+mod logging;
+mod metrics;
 mod server;
+mod tls;
 mod paths;
+mod security;
+
+use clap::Parser;
+use cli::{AuditAction, Cli, Command, ConfigAction, TokenAction};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     // Load environment variables from .env if present
     let _ = dotenvy::dotenv();
-    // Ensure data directories exist
-    paths::ensure_data_dirs().await?;
-    if let Err(e) = server::run_server().await {
-        eprintln!("Server failed to start: {:#}", e);
+    let cli = Cli::parse();
+    let config = match cli.load_config() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Configuration error: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let profile = cli.profile;
+    let command = cli.command.unwrap_or(Command::Serve);
+    if let Command::Config { action: ConfigAction::Check } = command {
+        print!("{}", config.to_redacted_toml()?);
+        if let Err(e) = config.validate() {
+            eprintln!("Configuration invalid: {:#}", e);
+            std::process::exit(1);
+        }
+        eprintln!("Configuration ok");
+        return Ok(());
+    }
+
+    if let Err(e) = config.validate() {
+        eprintln!("Configuration invalid: {:#}", e);
+        std::process::exit(1);
+    }
+    let selected = match config.profile(profile.as_deref()) {
+        Ok(p) => p.id.clone(),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+    paths::init(&config.paths.data_root)?;
+    let _log_guard = match logging::init(&config.logging) {
+        Ok(g) => g,
+        Err(e) => {
+            eprintln!("Logging setup failed: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(legacy) = paths::legacy_data_dir() {
+        tracing::warn!(
+            legacy = %legacy.display(),
+            data_root = %paths::data_root().display(),
+            "found entries in the old ./data folder; move them to the data root, or set paths.data_root / --data-root to keep using it"
+        );
+    }
+
+    // Commands that only touch configuration or credentials don't need the data key
+    let key_use = match &command {
+        Command::Passwd(_) | Command::Token { .. } | Command::Config { .. } => None,
+        Command::Encrypt => Some(crypto::KeyUse::Encrypt),
+        Command::Decrypt => Some(crypto::KeyUse::Decrypt),
+        _ if config.encryption.enabled => Some(crypto::KeyUse::Encrypt),
+        _ => Some(crypto::KeyUse::IfAvailable),
+    };
+    if let Some(key_use) = key_use {
+        if let Err(e) = crypto::init(&config.encryption, key_use).await {
+            tracing::error!("{:#}", e);
+            drop(_log_guard);
+            std::process::exit(1);
+        }
+    }
+
+    let result = match command {
+        Command::Serve => server::run_server(config).await.map_err(|e| e.context("server failed to start")),
+        Command::Add(args) => commands::add(&config, &selected, args).await,
+        Command::List(args) => commands::list(&config, &selected, args).await,
+        Command::Export(args) => commands::export(&config, &selected, args).await,
+        Command::Import(args) => commands::import(&config, &selected, args).await,
+        Command::Verify => {
+            // Everything unless a profile was named
+            let profiles: Vec<_> = match &profile {
+                Some(_) => config.profiles.iter().filter(|p| p.id == selected).collect(),
+                None => config.profiles.iter().collect(),
+            };
+            commands::verify(&config, &profiles).await
+        }
+        Command::Encrypt => commands::convert(&config, true).await,
+        Command::Decrypt => commands::convert(&config, false).await,
+        Command::Audit { action: AuditAction::List { entry, limit, json } } => commands::audit_list(profile.as_deref(), entry.as_deref(), limit, json).await,
+        Command::Audit { action: AuditAction::Verify } => commands::audit_verify().await,
+        Command::Passwd(args) => commands::passwd(&config, args).await,
+        Command::Token { action: TokenAction::Create { name, scope, expires } } => commands::token_create(&config, &name, scope, expires.as_deref()).await,
+        Command::Token { action: TokenAction::List { json } } => commands::token_list(&config, json).await,
+        Command::Token { action: TokenAction::Revoke { id } } => commands::token_revoke(&config, &id).await,
+        Command::Config { .. } => Ok(()),
+    };
+    if let Err(e) = result {
+        tracing::error!("{:#}", e);
+        drop(_log_guard);
         std::process::exit(1);
     }
     Ok(())
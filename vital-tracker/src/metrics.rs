@@ -0,0 +1,112 @@
+use axum::extract::MatchedPath;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use crate::imaging::StageTimings;
+
+/// Process-wide metrics, exposed in the Prometheus text format on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    entries_added: IntCounterVec,
+    /// Entry metadata files on disk per profile; refreshed on every scrape
+    pub entries_stored: IntGaugeVec,
+    image_stage: HistogramVec,
+    image_total: Histogram,
+    influx_writes: IntCounterVec,
+    /// Background Influx writes queued or in flight
+    pub influx_outbox: IntGauge,
+    /// 1 while the runtime breaker has switched Influx writes off
+    pub influx_disabled: IntGauge,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+const IMAGE_BUCKETS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("vital".to_string()), None).expect("metrics registry");
+        let http_requests = IntCounterVec::new(Opts::new("http_requests_total", "HTTP requests by route, method and status"), &["method", "route", "status"]).unwrap();
+        let http_duration = HistogramVec::new(HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"), &["method", "route"]).unwrap();
+        let entries_added = IntCounterVec::new(Opts::new("entries_added_total", "Entries stored since the server started, by profile"), &["profile"]).unwrap();
+        let entries_stored = IntGaugeVec::new(Opts::new("entries_stored", "Entries in the data root, by profile"), &["profile"]).unwrap();
+        let image_stage = HistogramVec::new(
+            HistogramOpts::new("image_stage_duration_seconds", "Time spent per photo pipeline stage").buckets(IMAGE_BUCKETS.to_vec()),
+            &["stage"],
+        )
+        .unwrap();
+        let image_total = Histogram::with_opts(HistogramOpts::new("image_processing_duration_seconds", "Total photo pipeline time per entry").buckets(IMAGE_BUCKETS.to_vec())).unwrap();
+        let influx_writes = IntCounterVec::new(Opts::new("influx_writes_total", "Influx entry writes by result"), &["result"]).unwrap();
+        let influx_outbox = IntGauge::new("influx_outbox_depth", "Influx writes queued or in flight").unwrap();
+        let influx_disabled = IntGauge::new("influx_disabled_runtime", "1 when Influx writes were switched off after a connection failure").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(entries_added.clone())).unwrap();
+        registry.register(Box::new(entries_stored.clone())).unwrap();
+        registry.register(Box::new(image_stage.clone())).unwrap();
+        registry.register(Box::new(image_total.clone())).unwrap();
+        registry.register(Box::new(influx_writes.clone())).unwrap();
+        registry.register(Box::new(influx_outbox.clone())).unwrap();
+        registry.register(Box::new(influx_disabled.clone())).unwrap();
+        // Pre-create the result series so rate() works before the first write
+        influx_writes.with_label_values(&["success"]);
+        influx_writes.with_label_values(&["failure"]);
+
+        Metrics {
+            registry,
+            http_requests,
+            http_duration,
+            entries_added,
+            entries_stored,
+            image_stage,
+            image_total,
+            influx_writes,
+            influx_outbox,
+            influx_disabled,
+        }
+    }
+
+    pub fn entry_added(&self, profile: &str) {
+        self.entries_added.with_label_values(&[profile]).inc();
+    }
+
+    pub fn observe_image(&self, t: &StageTimings) {
+        for (stage, d) in [("decode", t.decode), ("quality", t.quality), ("resize", t.resize), ("composite", t.composite), ("encode", t.encode)] {
+            self.image_stage.with_label_values(&[stage]).observe(d.as_secs_f64());
+        }
+        self.image_total.observe(t.total().as_secs_f64());
+    }
+
+    pub fn influx_write(&self, ok: bool) {
+        self.influx_writes.with_label_values(&[if ok { "success" } else { "failure" }]).inc();
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut out) {
+            tracing::error!(error = %e, "encoding metrics failed");
+        }
+        String::from_utf8(out).unwrap_or_default()
+    }
+}
+
+/// Counts requests and their latency per matched route. The route template (`/entry/:ts`)
+/// is used rather than the raw path so ids don't create a series each.
+pub async fn track_requests<B>(req: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()).unwrap_or_else(|| "unmatched".to_string());
+    let response = next.run(req).await;
+    let status = response.status().as_u16().to_string();
+    METRICS.http_requests.with_label_values(&[&method, &route, &status]).inc();
+    METRICS.http_duration.with_label_values(&[&method, &route]).observe(start.elapsed().as_secs_f64());
+    response
+}
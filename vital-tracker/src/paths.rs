@@ -1,13 +1,144 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
 use tokio::fs;
-pub const JSON_DIR: &str = "data/json";
-pub const PHOTOS_DIR: &str = "data/photos";
 
-pub async fn ensure_data_dirs() -> std::io::Result<()> {
-    fs::create_dir_all(JSON_DIR).await?;
-    fs::create_dir_all(PHOTOS_DIR).await?;
+static DATA_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Per-user data directory: `$XDG_DATA_HOME/vital-tracker` (usually ~/.local/share) on Linux,
+/// %APPDATA%\vital-tracker on Windows. Falls back to ./data where the platform has none.
+pub fn default_data_root() -> PathBuf {
+    dirs::data_dir().map(|d| d.join("vital-tracker")).unwrap_or_else(|| PathBuf::from("data"))
+}
+
+/// Sets the directory all data lives under, made absolute so later working-directory
+/// changes do not matter. Only the first call has any effect.
+pub fn init(root: &Path) -> std::io::Result<()> {
+    let _ = DATA_ROOT.set(std::path::absolute(root)?);
     Ok(())
 }
 
-pub fn json_meta_path(base_name: &str) -> String {
-    format!("{}/{}.json", JSON_DIR, base_name)
+pub fn data_root() -> &'static Path {
+    DATA_ROOT.get_or_init(default_data_root)
+}
+
+/// `./data` from before the data root was configurable, if it holds entries and is not the
+/// configured root.
+pub fn legacy_data_dir() -> Option<PathBuf> {
+    let legacy = std::path::absolute("data").ok()?;
+    (legacy != data_root() && legacy.join("json").is_dir()).then_some(legacy)
+}
+
+pub fn logs_dir() -> PathBuf {
+    data_root().join("logs")
+}
+
+/// The profile whose data lives directly in the data root, as it did before profiles existed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Every profile directory under the data root, including ones no longer configured.
+pub fn profiles_dir() -> PathBuf {
+    data_root().join("profiles")
+}
+
+/// Where one profile's entry metadata, photos and blobs live: the data root itself for the
+/// default profile, `profiles/<id>` for the others.
+#[derive(Debug, Clone)]
+pub struct ProfileDirs {
+    root: PathBuf,
+}
+
+impl ProfileDirs {
+    pub fn new(profile: &str) -> Self {
+        let root = if profile == DEFAULT_PROFILE { data_root().to_path_buf() } else { profiles_dir().join(profile) };
+        ProfileDirs { root }
+    }
+
+    /// Profile directories under an arbitrary root, so tests need not touch the data root.
+    #[cfg(test)]
+    pub fn at(root: PathBuf) -> Self {
+        ProfileDirs { root }
+    }
+
+    pub fn json_dir(&self) -> PathBuf {
+        self.root.join("json")
+    }
+
+    pub fn photos_dir(&self) -> PathBuf {
+        self.root.join("photos")
+    }
+
+    pub fn views_dir(&self) -> PathBuf {
+        self.photos_dir().join("views")
+    }
+
+    pub fn blobs_dir(&self) -> PathBuf {
+        self.root.join("blobs")
+    }
+
+    pub async fn ensure(&self) -> std::io::Result<()> {
+        fs::create_dir_all(self.json_dir()).await?;
+        fs::create_dir_all(self.photos_dir()).await?;
+        fs::create_dir_all(self.views_dir()).await?;
+        fs::create_dir_all(self.blobs_dir()).await?;
+        Ok(())
+    }
+
+    pub fn json_meta_path(&self, base_name: &str) -> PathBuf {
+        self.json_dir().join(format!("{}.json", base_name))
+    }
+
+    pub fn blob_index_path(&self) -> PathBuf {
+        self.blobs_dir().join("index.json")
+    }
+
+    /// Locked by every process while it changes the blob index or collects garbage.
+    pub fn blob_lock_path(&self) -> PathBuf {
+        self.blobs_dir().join("index.lock")
+    }
+
+    /// Maps a public `/photos/...` URL path back to the file on disk.
+    pub fn photo_file_path(&self, url_path: &str) -> PathBuf {
+        self.photos_dir().join(url_path.trim_start_matches("/photos/"))
+    }
+}
+
+/// True when `name` is a relative path made only of normal components, so joining it onto a
+/// directory cannot leave it. Backslashes are refused too, as Windows treats them as separators.
+pub fn is_contained(name: &str) -> bool {
+    !name.is_empty() && !name.contains('\\') && Path::new(name).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Generated self-signed certificate and key.
+pub fn tls_dir() -> PathBuf {
+    data_root().join("tls")
+}
+
+/// Key derivation salt and check value for encryption at rest.
+pub fn encryption_params_path() -> PathBuf {
+    data_root().join("encryption.json")
+}
+
+/// Password hash, sessions and API tokens.
+pub fn auth_path() -> PathBuf {
+    data_root().join("auth.json")
+}
+
+/// Append-only, hash-chained record of data changes.
+pub fn audit_log_path() -> PathBuf {
+    data_root().join("audit.jsonl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contained_names() {
+        for ok in ["a.jpg", "views/123_neck.jpg", "dashboard/graphs.html"] {
+            assert!(is_contained(ok), "{}", ok);
+        }
+        for bad in ["", "..", "../auth.json", "views/../../auth.json", "/etc/passwd", "..\\auth.json", "views\\x.jpg", "./a.jpg"] {
+            assert!(!is_contained(bad), "{}", bad);
+        }
+    }
 }
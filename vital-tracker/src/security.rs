@@ -0,0 +1,151 @@
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+
+/// Scripts only from this server plus the exact Chart.js and zoom plugin files index.html
+/// loads; allowing the whole CDN would let a page pull in any package it hosts. Inline
+/// `style` attributes are still used by the UI markup. `frame-ancestors` stops other sites
+/// framing the app.
+const CONTENT_SECURITY_POLICY: &str = "default-src 'self'; \
+script-src 'self' https://cdn.jsdelivr.net/npm/chart.js@4.4.1/dist/chart.umd.js https://cdn.jsdelivr.net/npm/chartjs-plugin-zoom@2.0.1/dist/chartjs-plugin-zoom.min.js; \
+style-src 'self' 'unsafe-inline'; img-src 'self' blob: data:; connect-src 'self'; object-src 'none'; base-uri 'none'; form-action 'self'; frame-ancestors 'none'";
+
+/// Origins besides the server's own that may send changing requests.
+pub struct OriginPolicy {
+    pub allowed: Vec<String>,
+}
+
+impl OriginPolicy {
+    /// Browsers label requests with `Sec-Fetch-Site` and `Origin`; anything from another
+    /// site is refused. Requests carrying neither come from scripts and the command line,
+    /// which authenticate with tokens a foreign page cannot attach.
+    fn check(&self, headers: &HeaderMap) -> Result<(), String> {
+        let get = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let origin = get("origin").map(|o| o.trim_end_matches('/'));
+        if origin.is_some_and(|o| self.allowed.iter().any(|a| a.trim_end_matches('/').eq_ignore_ascii_case(o))) {
+            return Ok(());
+        }
+        match get("sec-fetch-site") {
+            Some("same-origin") | Some("none") => return Ok(()),
+            Some(site) => return Err(format!("cross-site request refused (Sec-Fetch-Site: {})", site)),
+            None => {}
+        }
+        match origin {
+            None => Ok(()),
+            Some(o) => {
+                // Host names are case-insensitive, and browsers do not always lowercase Host
+                let origin_host = o.split_once("://").map(|(_, h)| h);
+                if origin_host.zip(get("host")).is_some_and(|(o, h)| o.eq_ignore_ascii_case(h)) {
+                    Ok(())
+                } else {
+                    Err(format!("cross-origin request refused (Origin: {})", o))
+                }
+            }
+        }
+    }
+}
+
+/// Refuses POST, PATCH, DELETE and other changing requests sent by a page from another
+/// origin, so a site open in the same browser cannot submit or delete entries with the
+/// user's session.
+pub async fn same_origin<B>(State(policy): State<Arc<OriginPolicy>>, req: Request<B>, next: Next<B>) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(req).await;
+    }
+    match policy.check(req.headers()) {
+        Ok(()) => next.run(req).await,
+        Err(why) => {
+            tracing::warn!(method = %req.method(), path = req.uri().path(), "{}", why);
+            (StatusCode::FORBIDDEN, why).into_response()
+        }
+    }
+}
+
+/// Adds no-sniff, referrer and framing headers to every response, and the content security
+/// policy to HTML pages.
+pub async fn security_headers<B>(req: Request<B>, next: Next<B>) -> Response {
+    let mut res = next.run(req).await;
+    let is_html = res.headers().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_some_and(|t| t.starts_with("text/html"));
+    let headers = res.headers_mut();
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("same-origin"));
+    if is_html {
+        headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static(CONTENT_SECURITY_POLICY));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(allowed: &[&str], headers: &[(&'static str, &'static str)]) -> Result<(), String> {
+        let policy = OriginPolicy { allowed: allowed.iter().map(|a| a.to_string()).collect() };
+        let mut map = HeaderMap::new();
+        for (k, v) in headers {
+            map.insert(*k, HeaderValue::from_static(v));
+        }
+        policy.check(&map)
+    }
+
+    #[test]
+    fn same_origin_is_allowed() {
+        assert!(check(&[], &[("sec-fetch-site", "same-origin"), ("origin", "http://127.0.0.1:8081")]).is_ok());
+        assert!(check(&[], &[("origin", "http://127.0.0.1:8081"), ("host", "127.0.0.1:8081")]).is_ok());
+        assert!(check(&[], &[("origin", "https://Tracker.Local:8443/"), ("host", "tracker.local:8443")]).is_ok());
+    }
+
+    #[test]
+    fn cross_site_is_refused() {
+        let err = check(&[], &[("sec-fetch-site", "cross-site"), ("origin", "https://evil.example")]).unwrap_err();
+        assert!(err.contains("Sec-Fetch-Site: cross-site"), "{}", err);
+        assert!(check(&[], &[("sec-fetch-site", "same-site"), ("origin", "http://other.local:8081")]).is_err());
+        let err = check(&[], &[("origin", "https://evil.example"), ("host", "127.0.0.1:8081")]).unwrap_err();
+        assert!(err.contains("Origin: https://evil.example"), "{}", err);
+        // Same host on another port is another origin
+        assert!(check(&[], &[("origin", "http://127.0.0.1:9999"), ("host", "127.0.0.1:8081")]).is_err());
+    }
+
+    #[test]
+    fn user_initiated_navigation_is_allowed() {
+        assert!(check(&[], &[("sec-fetch-site", "none")]).is_ok());
+    }
+
+    #[test]
+    fn configured_origin_is_allowed() {
+        let allowed = ["https://tracker.example.org/"];
+        let headers = [("sec-fetch-site", "cross-site"), ("origin", "https://TRACKER.example.org"), ("host", "10.0.0.5:8081")];
+        assert!(check(&allowed, &headers).is_ok());
+        assert!(check(&allowed, &[("origin", "https://tracker.example.org.evil.example"), ("host", "10.0.0.5:8081")]).is_err());
+    }
+
+    #[test]
+    fn requests_without_browser_headers_are_allowed() {
+        assert!(check(&[], &[]).is_ok());
+        assert!(check(&[], &[("host", "127.0.0.1:8081")]).is_ok());
+    }
+
+    #[test]
+    fn opaque_origin_is_refused() {
+        // Sandboxed frames and some redirects send `Origin: null`
+        let err = check(&[], &[("origin", "null"), ("host", "127.0.0.1:8081")]).unwrap_err();
+        assert!(err.contains("Origin: null"), "{}", err);
+        assert!(check(&[], &[("origin", "null")]).is_err());
+    }
+
+    #[test]
+    fn policy_allows_only_the_pinned_chart_scripts() {
+        let script_src = CONTENT_SECURITY_POLICY.split(';').map(str::trim).find(|d| d.starts_with("script-src")).unwrap();
+        let sources: Vec<&str> = script_src.split_whitespace().skip(1).collect();
+        let index = include_str!("../static/index.html");
+        let external: Vec<&str> = index.split("<script src=\"").skip(1).filter_map(|s| s.split('"').next()).filter(|s| s.starts_with("https://")).collect();
+        assert_eq!(external.len(), 2);
+        for src in &external {
+            assert!(sources.contains(src), "{} is not in the policy", src);
+        }
+        assert!(sources.iter().all(|s| *s == "'self'" || external.contains(s)), "{:?}", sources);
+    }
+}
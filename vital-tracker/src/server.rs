@@ -1,59 +1,358 @@
-use axum::{routing::{post, get, get_service, delete}, Router, extract::{Multipart, Path}, response::IntoResponse, http::StatusCode, Json};
-use std::net::SocketAddr;
-use chrono::Utc;
+use axum::{middleware, routing::{post, get, delete}, Router, extract::{ConnectInfo, DefaultBodyLimit, Extension, Multipart, Path, Query, State}, response::{IntoResponse, Redirect, Response}, http::{header, HeaderMap, StatusCode}, Form, Json};
 use tokio::fs;
 use anyhow::Result;
 use crate::db::influx::InfluxClient;
-use image::io::Reader as ImageReader;
-use image::DynamicImage;
-use tower_http::services::ServeDir;
+use crate::assets;
+use crate::audit::{self, Action, Event};
+use crate::auth::{self, AuthStore, LoginThrottle, Principal, Scope};
+use crate::health;
+use crate::logging;
+use crate::metrics::{self, METRICS};
 use crate::paths;
-use serde::{Serialize, Deserialize};
+use crate::security::{self, OriginPolicy};
+use crate::limits::{self, RateLimiter};
+use crate::tls;
+use crate::config::{Config, Features, ProfileConfig};
+use crate::crypto;
+use crate::blobstore::BlobStore;
+use crate::bp::Guideline;
+use crate::entries::{self, AddError, Entry, EntryChanges, EntryStore, NewEntry};
+use crate::imaging;
+use crate::imaging::output;
+use serde::Deserialize;
+use tracing::Instrument;
+use std::collections::BTreeMap;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio_util::task::TaskTracker;
 
 // If Influx is unreachable, we flip this flag to stop further background attempts/logs
 static INFLUX_DISABLED_RUNTIME: AtomicBool = AtomicBool::new(false);
 static INFLUX_LOGGED_ONCE: AtomicBool = AtomicBool::new(false);
 
-#[derive(Serialize, Deserialize)]
-struct Entry { path: String, sys: i64, dia: i64, pulse: i64, temp_c: f64, temp_jaw: Option<f64>, temp_room: Option<f64>, #[serde(alias = "pain_hr")] pain: Option<i64>, timestamp_nanos: i128 }
+/// Settings resolved once at startup and shared with every handler.
+struct AppState {
+    /// Configured profiles in order; the first one is used when a request names none
+    profiles: Vec<ProfileConfig>,
+    /// Entry store per profile id
+    stores: BTreeMap<String, EntryStore>,
+    features: Features,
+    /// Blood pressure categories used unless a request asks for another guideline
+    guideline: Guideline,
+    /// UI directory overriding the embedded assets
+    static_dir: Option<PathBuf>,
+    /// `None` when the Influx feature is switched off
+    influx: Option<InfluxClient>,
+    /// Entry writes and Influx sends that must finish before the process exits
+    tasks: TaskTracker,
+    min_free_disk_mb: u64,
+    started: Instant,
+    auth: Arc<AuthStore>,
+    login_throttle: LoginThrottle,
+    /// `false` when `auth.enabled` is off and every route is open
+    auth_enabled: bool,
+    /// Mark the session cookie `Secure`, i.e. HTTPS only
+    secure_cookies: bool,
+}
 
-pub async fn run_server() -> Result<()> {
-    let photos_service = get_service(ServeDir::new(paths::PHOTOS_DIR)).handle_error(|err: std::io::Error| async move {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("Unhandled internal error: {}", err))
+pub async fn run_server(config: Config) -> Result<()> {
+    let addr = config.server.addr()?;
+    let influx = config.features.influx.then(|| InfluxClient::new(&config.influx));
+    let auth = Arc::new(AuthStore::open(config.auth.session_ttl_hours).await?);
+    if config.auth.enabled && !auth.has_password().await {
+        return Err(anyhow::anyhow!(
+            "no password is set for the web UI; run `vital-tracker passwd` first (or set auth.enabled = false on a machine only you can reach)"
+        ));
+    }
+    if !config.auth.enabled {
+        tracing::warn!("authentication is disabled; anyone who can reach the port can read and change entries");
+    }
+    let tls = if config.tls.enabled { Some(tls::load(&config.tls, addr.ip()).await?) } else { None };
+    if let Some(tls) = &tls {
+        tracing::info!(fingerprint = %tls.fingerprint, self_signed = tls.self_signed, "TLS certificate (SHA-256)");
+    }
+    if !addr.ip().is_loopback() {
+        tracing::info!("listening beyond this machine; other devices on the network can connect");
+        if tls.is_none() {
+            tracing::warn!("LAN access over plain HTTP: passwords travel unencrypted and phone browsers will block the camera; enable [tls]");
+        }
+    }
+    let mut stores = BTreeMap::new();
+    for p in &config.profiles {
+        stores.insert(p.id.clone(), EntryStore::open(&config, &p.id).await?);
+    }
+    let state = Arc::new(AppState {
+        profiles: config.profiles.clone(),
+        stores,
+        features: config.features,
+        guideline: config.blood_pressure.guideline,
+        static_dir: config.paths.static_dir,
+        influx,
+        tasks: TaskTracker::new(),
+        min_free_disk_mb: config.server.min_free_disk_mb,
+        started: Instant::now(),
+        auth,
+        login_throttle: LoginThrottle::default(),
+        auth_enabled: config.auth.enabled,
+        secure_cookies: config.tls.enabled,
     });
+    let tasks = state.tasks.clone();
+    let grace = Duration::from_secs(config.server.shutdown_timeout_secs);
 
-    // Serve the static/ directory so files like /app.js and /dashboard/*.html are available
-    let static_service = get_service(ServeDir::new("static")).handle_error(|err: std::io::Error| async move {
-        (StatusCode::INTERNAL_SERVER_ERROR, format!("Unhandled internal error: {}", err))
-    });
+    // Reachable without logging in: health probes, the login form and the UI's own assets
+    let public = Router::new()
+        .route("/health", get(health))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
+        .route("/login", get(login_page).post(login))
+        .route("/logout", post(logout))
+        .route("/static/*name", get(static_asset));
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/", get(root))
-        .route("/health", get(health))
-        .nest_service("/static", static_service)
+        .route("/metrics", get(metrics_text))
+        .route("/profiles", get(list_profiles))
         .route("/entry", post(handle_entry))
-        .route("/entry/:ts", delete(delete_entry))
+        .route("/entry/:ts", delete(delete_entry).patch(edit_entry))
+        .route("/entry/:ts/restore", post(restore_entry))
         .route("/influx_last", get(influx_last))
         .route("/entries", get(list_entries))
-        .nest_service("/photos", photos_service);
+        .route("/bp/guideline", get(bp_guideline))
+        .route("/photos/*name", get(serve_photo))
+        .route("/blobs/gc", post(blobs_gc))
+        .route("/blobs/verify", get(blobs_verify))
+        .route("/api/v1/tokens", get(list_tokens).post(create_token))
+        .route("/api/v1/tokens/:id", delete(revoke_token))
+        .route("/audit", get(audit_log))
+        .route("/audit/verify", get(audit_verify));
+    if state.features.timelapse {
+        app = app.route("/timelapse", get(timelapse));
+    }
+    if state.features.compare {
+        app = app.route("/compare", get(compare));
+    }
+    let limiter = Arc::new(RateLimiter::new(&config.limits));
+    // Route layers wrap the ones added before them, so the global limit runs after auth
+    app = app.route_layer(middleware::from_fn_with_state(limiter.clone(), limits::limit_global_writes));
+    if state.auth_enabled {
+        app = app.route_layer(middleware::from_fn_with_state(state.auth.clone(), auth::require_auth));
+    }
+    let origins = Arc::new(OriginPolicy { allowed: config.server.allowed_origins.clone() });
+    let max_body = config.limits.max_body_mb * 1024 * 1024;
+    let app = public
+        .merge(app)
+        .layer(DefaultBodyLimit::max(max_body as usize))
+        .layer(middleware::from_fn_with_state(max_body, limits::limit_body))
+        .layer(middleware::from_fn_with_state(limiter, limits::limit_client_writes))
+        .layer(middleware::from_fn_with_state(origins, security::same_origin))
+        .layer(middleware::from_fn(security::security_headers))
+        .layer(middleware::from_fn(metrics::track_requests))
+        .layer(middleware::from_fn(logging::trace_requests))
+        .with_state(state);
+
+    // On a stop signal the listener closes and open requests get `grace` to finish; the
+    // deadline is passed on so background writes share the same budget
+    let handle = axum_server::Handle::new();
+    let (deadline_tx, deadline_rx) = oneshot::channel();
+    let signal_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!(timeout_secs = grace.as_secs(), "shutting down, waiting for in-flight requests");
+        let _ = deadline_tx.send(tokio::time::Instant::now() + grace);
+        signal_handle.graceful_shutdown(Some(grace));
+    });
+
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls) => {
+            tracing::info!(url = %format!("https://{}", addr), data_root = %paths::data_root().display(), "listening");
+            axum_server::bind_rustls(addr, tls.rustls).handle(handle).serve(service).await?;
+        }
+        None => {
+            tracing::info!(url = %format!("http://{}", addr), data_root = %paths::data_root().display(), "listening");
+            axum_server::bind(addr).handle(handle).serve(service).await?;
+        }
+    }
 
-    let addr = SocketAddr::from(([127,0,0,1], 8081));
-    println!("Listening on {}", addr);
-    axum::Server::bind(&addr).serve(app.into_make_service()).await?;
+    let deadline = deadline_rx.await.unwrap_or_else(|_| tokio::time::Instant::now() + grace);
+    tasks.close();
+    if tokio::time::timeout_at(deadline, tasks.wait()).await.is_err() {
+        tracing::warn!(pending = tasks.len(), "background writes still running at the shutdown timeout; abandoning them");
+    }
+    tracing::info!("server stopped");
     Ok(())
 }
 
-async fn root() -> impl IntoResponse {
-    let html = include_str!("../static/index.html");
-    (axum::http::StatusCode::OK, axum::response::Html(html))
+/// Resolves on Ctrl+C, SIGTERM on Unix, or a system shutdown on Windows.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!(error = %e, "cannot listen for Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(windows)]
+    let terminate = async {
+        match tokio::signal::windows::ctrl_shutdown() {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "cannot listen for system shutdown");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(any(unix, windows)))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Selects a profile on entry, photo and statistics routes: `?profile=<id>`.
+#[derive(Deserialize)]
+struct ProfileParam {
+    profile: Option<String>,
+}
+
+impl AppState {
+    /// The store of the requested profile, or of the first configured one; 404 for an unknown id.
+    fn store(&self, p: &ProfileParam) -> Result<&EntryStore, (StatusCode, String)> {
+        let id = p.profile.as_deref().filter(|id| !id.is_empty()).unwrap_or(&self.profiles[0].id);
+        self.stores.get(id).ok_or_else(|| (StatusCode::NOT_FOUND, format!("unknown profile '{}'", id)))
+    }
+}
+
+async fn root(State(state): State<Arc<AppState>>) -> Response {
+    assets::serve(state.static_dir.as_deref(), "index.html").await
+}
+
+async fn static_asset(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> Response {
+    assets::serve(state.static_dir.as_deref(), &name).await
+}
+
+fn session_cookie_header(state: &AppState, value: &str, max_age: i64) -> String {
+    let secure = if state.secure_cookies { "; Secure" } else { "" };
+    format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}", auth::SESSION_COOKIE, value, max_age, secure)
+}
+
+async fn login_page(State(state): State<Arc<AppState>>) -> Response {
+    if !state.auth_enabled {
+        return Redirect::to("/").into_response();
+    }
+    assets::serve(state.static_dir.as_deref(), "login.html").await
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    password: String,
+}
+
+async fn login(State(state): State<Arc<AppState>>, ConnectInfo(addr): ConnectInfo<SocketAddr>, Form(form): Form<LoginForm>) -> Response {
+    let client = addr.ip();
+    if let Err(wait) = state.login_throttle.begin(client, Instant::now()) {
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        tracing::warn!(client = %client, retry_after, "login attempt refused, too many failures");
+        let msg = format!("too many failed logins; retry in {} s", retry_after);
+        return (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after.to_string())], msg).into_response();
+    }
+    match state.auth.login(&form.password).await {
+        Ok(Some(secret)) => {
+            state.login_throttle.succeeded(client);
+            let cookie = session_cookie_header(&state, &secret, state.auth.session_ttl_secs());
+            ([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+        }
+        Ok(None) => {
+            tracing::warn!(client = %client, "failed login attempt");
+            audit::record(&format!("client:{}", client), Event::new(Action::LoginFailed)).await;
+            // Slows down password guessing through the form
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Redirect::to("/login?failed=1").into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("login error: {}", e)).into_response(),
+    }
+}
+
+async fn logout(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if let Some(secret) = auth::session_cookie(&headers) {
+        if let Err(e) = state.auth.logout(secret).await {
+            tracing::warn!(error = %e, "could not end session");
+        }
+    }
+    let cookie = session_cookie_header(&state, "", 0);
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/login")).into_response()
 }
 
 async fn health() -> impl IntoResponse {
     (StatusCode::OK, "ok")
 }
 
-async fn handle_entry(mut multipart: Multipart) -> impl IntoResponse {
+/// The process is up and serving requests; says nothing about its dependencies.
+async fn health_live(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "live",
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.started.elapsed().as_secs(),
+    }))
+}
+
+/// Checks everything an entry write depends on. 503 when a required check fails; Influx
+/// being down only marks the service degraded.
+async fn health_ready(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let blobs: Vec<(&str, &BlobStore)> = state.stores.iter().map(|(id, s)| (id.as_str(), &s.blobs)).collect();
+    let (data_dir, disk, storage, influx) = tokio::join!(
+        health::data_dir_writable(),
+        health::free_disk(state.min_free_disk_mb),
+        health::storage(&blobs),
+        health::influx(state.influx.as_ref(), INFLUX_DISABLED_RUNTIME.load(Ordering::Relaxed)),
+    );
+    let readiness = health::Readiness::from_checks([("data_dir", data_dir), ("disk_space", disk), ("storage", storage), ("influx", influx)].into());
+    let status = if readiness.is_ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(readiness))
+}
+
+async fn metrics_text(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    METRICS.influx_disabled.set(i64::from(INFLUX_DISABLED_RUNTIME.load(Ordering::Relaxed)));
+    for (id, store) in &state.stores {
+        METRICS.entries_stored.with_label_values(&[id]).set(entries::count_entries(&store.dirs).await as i64);
+    }
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], METRICS.render())
+}
+
+/// Configured profiles for the UI's switcher, the default first.
+async fn list_profiles(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let list: Vec<serde_json::Value> = state.profiles.iter().map(|p| serde_json::json!({ "id": p.id, "name": p.display_name() })).collect();
+    Json(list)
+}
+
+/// Who to name in the audit log for a request; `anonymous` when auth is switched off.
+fn actor(principal: Option<Extension<Principal>>) -> String {
+    principal.map(|Extension(p)| p.label()).unwrap_or_else(|| "anonymous".to_string())
+}
+
+async fn handle_entry(State(state): State<Arc<AppState>>, principal: Option<Extension<Principal>>, Query(p): Query<ProfileParam>, mut multipart: Multipart) -> Response {
+    let profile = match state.store(&p) {
+        Ok(store) => store.profile.clone(),
+        Err(res) => return res.into_response(),
+    };
     // Expected: sys, dia, pulse, temp, photo_front, photo_left, photo_right
     let mut sys: Option<i64> = None;
     let mut dia: Option<i64> = None;
@@ -67,7 +366,14 @@ async fn handle_entry(mut multipart: Multipart) -> impl IntoResponse {
     let mut temp_room: Option<f64> = None;
     let mut pain: Option<i64> = None;
 
-    while let Some(field) = multipart.next_field().await.unwrap_or(None) {
+    // A body over the size limit ends in an error here; stopping at it instead would store
+    // the entry without the photos that did not fit
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(f)) => f,
+            Ok(None) => break,
+            Err(e) => return (e.status(), e.body_text()).into_response(),
+        };
         let name = field.name().map(|s| s.to_string()).unwrap_or_default();
         match name.as_str() {
             "sys" => {
@@ -90,18 +396,22 @@ async fn handle_entry(mut multipart: Multipart) -> impl IntoResponse {
                     temp = text.parse().ok();
                 }
             }
-            "photo_front" => {
-                front = field.bytes().await.ok().map(|b| b.to_vec());
-            }
-            "photo_left" => {
-                left = field.bytes().await.ok().map(|b| b.to_vec());
-            }
-            "photo_right" => {
-                right = field.bytes().await.ok().map(|b| b.to_vec());
-            }
-            "photo_neck" => {
-                neck = field.bytes().await.ok().map(|b| b.to_vec());
-            }
+            "photo_front" => match field.bytes().await {
+                Ok(b) => front = Some(b.to_vec()),
+                Err(e) => return (e.status(), e.body_text()).into_response(),
+            },
+            "photo_left" => match field.bytes().await {
+                Ok(b) => left = Some(b.to_vec()),
+                Err(e) => return (e.status(), e.body_text()).into_response(),
+            },
+            "photo_right" => match field.bytes().await {
+                Ok(b) => right = Some(b.to_vec()),
+                Err(e) => return (e.status(), e.body_text()).into_response(),
+            },
+            "photo_neck" => match field.bytes().await {
+                Ok(b) => neck = Some(b.to_vec()),
+                Err(e) => return (e.status(), e.body_text()).into_response(),
+            },
             "temp_jaw" => {
                 if let Ok(text) = field.text().await {
                     temp_jaw = text.parse().ok();
@@ -121,96 +431,90 @@ async fn handle_entry(mut multipart: Multipart) -> impl IntoResponse {
         }
     }
 
-    if sys.is_none() || dia.is_none() || pulse.is_none() || temp.is_none() {
-        return (StatusCode::BAD_REQUEST, "Missing numeric fields".to_string());
-    }
-
-    let combined_path = match combine_and_save_images(front, left, right, neck, sys.unwrap(), dia.unwrap(), pulse.unwrap(), temp.unwrap(), temp_jaw, temp_room, pain).await {
-        Ok(p) => p,
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("image error: {}", e)),
+    let input = NewEntry { sys, dia, pulse, temp, temp_jaw, temp_room, pain, photos: [front, left, right, neck] };
+    // Run the write as a tracked task so a dropped connection can't abandon it half-way and
+    // shutdown waits for it
+    let (writer, id) = (state.clone(), profile.clone());
+    let actor = actor(principal);
+    let added = match state.tasks.spawn(async move { writer.stores[&id].add(input, &actor).await }.instrument(tracing::Span::current())).await {
+        Ok(Ok(a)) => a,
+        Ok(Err(e @ AddError::Invalid(_))) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Ok(Err(e @ AddError::Rejected(_))) => return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+        Ok(Err(e @ AddError::NoSpace(_))) => return (StatusCode::INSUFFICIENT_STORAGE, e.to_string()).into_response(),
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("image error: {}", e)).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("image error: {}", e)).into_response(),
     };
 
-    // Optionally write to Influx in the background (can be disabled via env or runtime circuit-breaker)
-    let disable_influx_env = std::env::var("VITAL_DISABLE_INFLUX").ok().map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false);
+    send_to_influx_later(&state, &state.stores[&profile], added.entry.clone());
+
+    let id = logging::EntryId(added.entry.timestamp_nanos.to_string());
+    if !added.warnings.is_empty() {
+        let lines: Vec<String> = added.warnings.iter().map(|w| format!("warning: {}", w)).collect();
+        return (StatusCode::OK, Extension(id), format!("ok\n{}", lines.join("\n"))).into_response();
+    }
+    (StatusCode::OK, Extension(id), "ok").into_response()
+}
+
+/// Writes an entry to Influx in the background, unless Influx is disabled in the configuration
+/// or by the runtime circuit-breaker. Points are written at the entry's timestamp, so an edit
+/// replaces the earlier point.
+fn send_to_influx_later(state: &Arc<AppState>, store: &EntryStore, entry: Entry) {
     let disable_influx_runtime = INFLUX_DISABLED_RUNTIME.load(Ordering::Relaxed);
-    if !disable_influx_env && !disable_influx_runtime {
-        let cp = combined_path.clone();
-        let s = sys.unwrap(); let d = dia.unwrap(); let p = pulse.unwrap(); let t = temp.unwrap();
-        tokio::spawn(async move {
-            if let Ok(client) = InfluxClient::from_env() {
-                if let Err(e) = client.write_entry(s, d, p, t, &cp).await {
+    if state.influx.is_some() && !disable_influx_runtime {
+        let state = state.clone();
+        let profile = store.profile.clone();
+        METRICS.influx_outbox.inc();
+        state.tasks.clone().spawn(async move {
+            if let Some(client) = &state.influx {
+                if let Err(e) = entries::send_to_influx(client, &state.stores[&profile], &entry).await {
                     let msg = e.to_string();
                     // Detect connection errors and disable future attempts for this session
                     if msg.contains("No connection could be made") || msg.contains("error trying to connect") {
                         INFLUX_DISABLED_RUNTIME.store(true, Ordering::Relaxed);
                         if !INFLUX_LOGGED_ONCE.swap(true, Ordering::Relaxed) {
-                            eprintln!(
-                                "Influx unreachable; disabling writes for this session. Set VITAL_DISABLE_INFLUX=1 to hide this. ({})",
-                                msg
-                            );
+                            tracing::warn!(error = %msg, "Influx unreachable; disabling writes for this session. Set features.influx = false (or VITAL_DISABLE_INFLUX=1) to hide this.");
                         }
                     } else {
-                        eprintln!("Influx write failed (background): {}", msg);
+                        tracing::error!(error = %msg, "Influx write failed");
                     }
                 }
-            } else {
-                eprintln!("Could not create Influx client (background)");
             }
-        });
+            METRICS.influx_outbox.dec();
+        }.instrument(tracing::Span::current()));
     }
+}
 
-    (StatusCode::OK, "ok".to_string())
-}
-
-async fn list_entries() -> impl IntoResponse {
-    let mut out: Vec<Entry> = Vec::new();
-    if let Ok(mut files) = fs::read_dir(paths::PHOTOS_DIR).await {
-        while let Ok(Some(entry)) = files.next_entry().await {
-            if let Ok(md) = entry.metadata().await {
-                if md.is_file() {
-                    if let Some(fname) = entry.file_name().to_str() {
-                        // look for .json metadata file matching the image name
-                        if fname.ends_with(".jpg") {
-                            let base = fname.trim_end_matches(".jpg");
-                            let meta_path_new = paths::json_meta_path(base);
-                            let meta_path_legacy = format!("{}/{}.json", paths::PHOTOS_DIR, base);
-                            // Try new location first, then legacy next-to-photo JSON
-                            let meta_json_res = match fs::read_to_string(&meta_path_new).await {
-                                Ok(j) => Ok(j),
-                                Err(_) => fs::read_to_string(&meta_path_legacy).await,
-                            };
-                            if let Ok(j) = meta_json_res {
-                                if let Ok(entry_meta) = serde_json::from_str::<Entry>(&j) {
-                                    out.push(entry_meta);
-                                } else {
-                                    // fallback: return minimal entry
-                                    let rel = format!("/photos/{}", fname);
-                                    if let Ok(ts) = base.parse::<i128>() {
-                                        out.push(Entry { path: rel, sys:0, dia:0, pulse:0, temp_c:0.0, temp_jaw: None, temp_room: None, pain: None, timestamp_nanos: ts });
-                                    } else {
-                                        out.push(Entry { path: rel, sys:0, dia:0, pulse:0, temp_c:0.0, temp_jaw: None, temp_room: None, pain: None, timestamp_nanos:0 });
-                                    }
-                                }
-                            } else {
-                                let rel = format!("/photos/{}", fname);
-                                if let Ok(ts) = base.parse::<i128>() {
-                                    out.push(Entry { path: rel, sys:0, dia:0, pulse:0, temp_c:0.0, temp_jaw: None, temp_room: None, pain: None, timestamp_nanos: ts });
-                                } else {
-                                    out.push(Entry { path: rel, sys:0, dia:0, pulse:0, temp_c:0.0, temp_jaw: None, temp_room: None, pain: None, timestamp_nanos:0 });
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    Json(out)
+/// Selects the blood pressure guideline for one request: `?guideline=aha|esc`.
+#[derive(Deserialize)]
+struct GuidelineParam {
+    guideline: Option<Guideline>,
+}
+
+async fn list_entries(State(state): State<Arc<AppState>>, Query(p): Query<ProfileParam>, Query(g): Query<GuidelineParam>) -> Response {
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    let guideline = g.guideline.unwrap_or(state.guideline);
+    let list: Vec<serde_json::Value> = entries::read_all_entries(&store.dirs).await.iter().map(|e| e.to_annotated_json(guideline)).collect();
+    Json(list).into_response()
 }
 
-async fn influx_last() -> impl IntoResponse {
-    match InfluxClient::from_env() {
-        Ok(c) => {
+/// Category thresholds of the selected guideline, for chart bands and classifying averages.
+async fn bp_guideline(State(state): State<Arc<AppState>>, Query(g): Query<GuidelineParam>) -> impl IntoResponse {
+    let guideline = g.guideline.unwrap_or(state.guideline);
+    Json(serde_json::json!({ "guideline": guideline, "name": guideline.name(), "bands": guideline.bands() }))
+}
+
+async fn influx_last(State(state): State<Arc<AppState>>, Query(p): Query<ProfileParam>) -> Response {
+    let profile = match state.store(&p) {
+        Ok(s) => s.profile.as_str(),
+        Err(res) => return res.into_response(),
+    };
+    // Points written before profiles existed have no tag and belong to the default profile
+    let is_default = profile == paths::DEFAULT_PROFILE;
+    let result = match &state.influx {
+        Some(c) => {
             // If an org is configured we assume Influx v2 and use a Flux query.
             // Otherwise fall back to InfluxQL (compatibility API).
             if c.org().is_some() {
@@ -218,9 +522,14 @@ async fn influx_last() -> impl IntoResponse {
                 // Fetch recent points for measurement vital_entry and return the most recent one.
                 // This Flux query returns the latest record for the measurement; fields will be
                 // returned in separate rows (CSV) which is fine for quick verification.
+                let profile_filter = if is_default {
+                    format!("(not exists r.profile or r.profile == \"{}\")", profile)
+                } else {
+                    format!("r.profile == \"{}\"", profile)
+                };
                 let flux = format!(
-                    "from(bucket:\"{}\") |> range(start: -30d) |> filter(fn: (r) => r._measurement == \"vital_entry\") |> sort(columns: [\"_time\"], desc: true) |> limit(n:1)",
-                    bucket
+                    "from(bucket:\"{}\") |> range(start: -30d) |> filter(fn: (r) => r._measurement == \"vital_entry\" and {}) |> sort(columns: [\"_time\"], desc: true) |> limit(n:1)",
+                    bucket, profile_filter
                 );
                 match c.query_influxql(&flux).await {
                     Ok(body) => (StatusCode::OK, body),
@@ -228,104 +537,352 @@ async fn influx_last() -> impl IntoResponse {
                 }
             } else {
                 // use InfluxQL compat endpoint to return last point for measurement vital_entry
-                let q = "SELECT * FROM vital_entry ORDER BY time DESC LIMIT 1";
-                match c.query_influxql(q).await {
+                let profile_filter = if is_default {
+                    format!("(profile = '{}' OR profile = '')", profile)
+                } else {
+                    format!("profile = '{}'", profile)
+                };
+                let q = format!("SELECT * FROM vital_entry WHERE {} ORDER BY time DESC LIMIT 1", profile_filter);
+                match c.query_influxql(&q).await {
                     Ok(body) => (StatusCode::OK, body),
                     Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("query error: {}", e)),
                 }
             }
         }
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("influx client error: {}", e)),
+        None => (StatusCode::SERVICE_UNAVAILABLE, "influx is disabled in the configuration".to_string()),
+    };
+    result.into_response()
+}
+
+async fn delete_entry(State(state): State<Arc<AppState>>, principal: Option<Extension<Principal>>, Path(ts): Path<String>, Query(p): Query<ProfileParam>) -> Response {
+    if !entries::is_valid_id(&ts) {
+        return (StatusCode::BAD_REQUEST, "invalid entry id").into_response();
     }
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    store.delete(&ts, &actor(principal)).await;
+    (StatusCode::NO_CONTENT, Extension(logging::EntryId(ts))).into_response()
 }
 
-async fn combine_and_save_images(front: Option<Vec<u8>>, left: Option<Vec<u8>>, right: Option<Vec<u8>>, neck: Option<Vec<u8>>, sys: i64, dia: i64, pulse: i64, temp_c: f64, temp_jaw: Option<f64>, temp_room: Option<f64>, pain: Option<i64>) -> Result<String> {
-    let photos_dir = paths::PHOTOS_DIR;
-    fs::create_dir_all(photos_dir).await?;
+async fn edit_entry(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Path(ts): Path<String>,
+    Query(p): Query<ProfileParam>,
+    Json(changes): Json<EntryChanges>,
+) -> Response {
+    if !entries::is_valid_id(&ts) {
+        return (StatusCode::BAD_REQUEST, "invalid entry id").into_response();
+    }
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    match store.edit(&ts, changes, &actor(principal)).await {
+        Ok(Some(entry)) => {
+            send_to_influx_later(&state, store, entry.clone());
+            (Extension(logging::EntryId(ts)), Json(entry)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, format!("no entry {}", ts)).into_response(),
+        Err(e @ AddError::Invalid(_)) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("edit error: {}", e)).into_response(),
+    }
+}
 
-    async fn decode_image(bytes: Vec<u8>) -> Result<DynamicImage> {
-        let img = ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?.decode()?;
-        Ok(img)
+async fn restore_entry(State(state): State<Arc<AppState>>, principal: Option<Extension<Principal>>, Path(ts): Path<String>, Query(p): Query<ProfileParam>) -> Response {
+    if !entries::is_valid_id(&ts) {
+        return (StatusCode::BAD_REQUEST, "invalid entry id").into_response();
+    }
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    match store.restore(&ts, &actor(principal)).await {
+        Ok(Some(restored)) => {
+            send_to_influx_later(&state, store, restored.entry.clone());
+            let body = serde_json::json!({ "entry": restored.entry, "missing_photos": restored.missing_photos });
+            (Extension(logging::EntryId(ts)), Json(body)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, format!("no deleted entry {} in the audit log", ts)).into_response(),
+        Err(e @ AddError::Invalid(_)) => (StatusCode::CONFLICT, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("restore error: {}", e)).into_response(),
     }
+}
 
-    let img_front = match front { Some(b) => Some(decode_image(b).await?), None => None };
-    let img_left = match left { Some(b) => Some(decode_image(b).await?), None => None };
-    let img_right = match right { Some(b) => Some(decode_image(b).await?), None => None };
-    let img_neck = match neck { Some(b) => Some(decode_image(b).await?), None => None };
+#[derive(Deserialize)]
+struct TimelapseQuery {
+    view: String,
+    /// Inclusive local dates, YYYY-MM-DD
+    from: Option<String>,
+    to: Option<String>,
+    fps: Option<f32>,
+    height: Option<u32>,
+    overlay: Option<bool>,
+}
 
-    if img_front.is_none() && img_left.is_none() && img_right.is_none() && img_neck.is_none() {
-        return Err(anyhow::anyhow!("no images provided"));
+/// Where to find `view` for an entry: its own photo, or the composite to crop it from (`true`).
+async fn view_source(blobs: &BlobStore, e: &Entry, view: &str) -> (PathBuf, bool) {
+    match e.views.get(view) {
+        Some(p) => (blobs.resolve(p).await, false),
+        None => (blobs.resolve(&e.path).await, true),
     }
+}
 
-    let height = [img_front.as_ref(), img_left.as_ref(), img_right.as_ref(), img_neck.as_ref()]
-        .iter()
-        .filter_map(|i| i.as_ref().map(|img| img.height()))
-        .max()
-        .unwrap_or(480);
+async fn timelapse(State(state): State<Arc<AppState>>, Query(q): Query<TimelapseQuery>, Query(p): Query<ProfileParam>) -> Response {
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    let view_index = match imaging::VIEW_NAMES.iter().position(|v| *v == q.view) {
+        Some(i) => i,
+        None => return (StatusCode::BAD_REQUEST, format!("unknown view '{}', expected one of {}", q.view, imaging::VIEW_NAMES.join(", "))).into_response(),
+    };
+    let from = match q.from.as_deref().map(entries::parse_day).transpose() {
+        Ok(d) => d.map(entries::local_day_start_nanos).unwrap_or(i128::MIN),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let to = match q.to.as_deref().map(entries::parse_day).transpose() {
+        Ok(d) => d.and_then(|d| d.succ_opt()).map(entries::local_day_start_nanos).unwrap_or(i128::MAX),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    if q.fps.is_some_and(|f| !f.is_finite()) {
+        return (StatusCode::BAD_REQUEST, "fps must be a finite number".to_string()).into_response();
+    }
+    let defaults = imaging::timelapse::TimelapseOptions::default();
+    let opts = imaging::timelapse::TimelapseOptions {
+        fps: q.fps.unwrap_or(defaults.fps),
+        height: q.height.unwrap_or(defaults.height).min(1080),
+        overlay_dates: q.overlay.unwrap_or(defaults.overlay_dates),
+    };
 
-    fn blank_image(width: u32, height: u32) -> DynamicImage {
-        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(width, height, image::Rgb([220,220,220])))
+    let mut entries: Vec<Entry> = entries::read_all_entries(&store.dirs).await.into_iter().filter(|e| e.timestamp_nanos >= from && e.timestamp_nanos < to).collect();
+    entries.sort_by_key(|e| e.timestamp_nanos);
+    // Per-view photo when we have one, otherwise try to cut the view out of a legacy composite
+    let mut sources: Vec<(PathBuf, bool, String)> = Vec::with_capacity(entries.len());
+    for e in &entries {
+        let (file, legacy) = view_source(&store.blobs, e, &q.view).await;
+        sources.push((file, legacy, entries::local_time_label(e.timestamp_nanos)));
     }
+    let sources = imaging::timelapse::subsample(sources, imaging::timelapse::MAX_FRAMES);
 
-    let mut resized: Vec<DynamicImage> = Vec::new();
-    for img_opt in [&img_front, &img_left, &img_right, &img_neck] {
-        if let Some(img) = img_opt {
-            let w = ((img.width() as f32) * (height as f32) / (img.height() as f32)) as u32;
-            let r = img.resize_exact(w, height, image::imageops::FilterType::Triangle);
-            resized.push(r);
-        } else {
-            // Use a 4:3 blank placeholder if missing
-            resized.push(blank_image(height * 4 / 3, height));
-        }
+    let rendered = tokio::task::spawn_blocking(move || -> Result<Option<Vec<u8>>> {
+        // Decoded lazily so only one full-size photo is held at a time
+        let frames = sources.into_iter().filter_map(|(file, legacy, label)| {
+            imaging::load_view(&file, legacy, view_index).map(|image| imaging::timelapse::TimelapseFrame { image, label })
+        });
+        imaging::timelapse::render_gif(frames, &opts)
+    }).await;
+
+    match rendered {
+        Ok(Ok(Some(gif))) => ([(header::CONTENT_TYPE, "image/gif")], gif).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, format!("no photos of the {} view in the selected range", q.view)).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("timelapse error: {}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("timelapse error: {}", e)).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    a: String,
+    b: String,
+    /// View to compare; the full composites are used when omitted or empty
+    view: Option<String>,
+}
+
+fn comparison_caption(e: &Entry) -> Vec<String> {
+    let mut vitals = format!("BP {}/{}  P {}  T {:.1}", e.sys, e.dia, e.pulse, e.temp_c);
+    if let Some(jaw) = e.temp_jaw {
+        vitals.push_str(&format!("  JAW {:.1}", jaw));
     }
+    if let Some(pain) = e.pain {
+        vitals.push_str(&format!("  PAIN {}", pain));
+    }
+    vec![entries::local_time_label(e.timestamp_nanos), vitals]
+}
 
-    let total_width: u32 = resized.iter().map(|i| i.width()).sum();
-    let mut imgbuf = image::RgbImage::new(total_width, height);
+async fn compare(State(state): State<Arc<AppState>>, Query(q): Query<CompareQuery>, Query(p): Query<ProfileParam>) -> Response {
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    let view = q.view.as_deref().filter(|v| !v.is_empty());
+    let view_index = match view {
+        Some(v) => match imaging::VIEW_NAMES.iter().position(|n| *n == v) {
+            Some(i) => Some(i),
+            None => return (StatusCode::BAD_REQUEST, format!("unknown view '{}', expected one of {}", v, imaging::VIEW_NAMES.join(", "))).into_response(),
+        },
+        None => None,
+    };
 
-    let mut x = 0u32;
-    for img in resized {
-        let rgb = img.to_rgb8();
-        for (px, py, pixel) in rgb.enumerate_pixels() {
-            imgbuf.put_pixel(x + px, py, *pixel);
-        }
-        x += rgb.width();
+    let entries = entries::read_all_entries(&store.dirs).await;
+    let find = |ts: &str| entries.iter().find(|e| e.timestamp_nanos.to_string() == ts.trim());
+    let (ea, eb) = match (find(&q.a), find(&q.b)) {
+        (Some(a), Some(b)) => (a, b),
+        (None, _) => return (StatusCode::NOT_FOUND, format!("entry {} not found", q.a)).into_response(),
+        (_, None) => return (StatusCode::NOT_FOUND, format!("entry {} not found", q.b)).into_response(),
+    };
+    let mut sides = Vec::new();
+    for e in [ea, eb] {
+        let source = match view {
+            Some(v) => view_source(&store.blobs, e, v).await,
+            None => (store.blobs.resolve(&e.path).await, false),
+        };
+        sides.push((source, comparison_caption(e)));
+    }
+
+    let opts = store.image.clone();
+    let opts_mime = opts.format.mime_type();
+    let rendered = tokio::task::spawn_blocking(move || -> Result<Option<Vec<u8>>> {
+        let mut sides = sides.into_iter();
+        let (Some(((fa, la), ca)), Some(((fb, lb), cb))) = (sides.next(), sides.next()) else {
+            return Ok(None);
+        };
+        let idx = view_index.unwrap_or(0);
+        let (Some(ia), Some(ib)) = (imaging::load_view(&fa, la, idx), imaging::load_view(&fb, lb, idx)) else {
+            return Ok(None);
+        };
+        let img = imaging::compare::render_comparison(
+            imaging::compare::ComparisonSide { image: ia, caption: ca },
+            imaging::compare::ComparisonSide { image: ib, caption: cb },
+        )?;
+        let img = output::fit_within(img, opts.max_dimension);
+        output::encode(&img, &opts).map(Some)
+    }).await;
+
+    match rendered {
+        Ok(Ok(Some(bytes))) => ([(header::CONTENT_TYPE, opts_mime)], bytes).into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "photo not available for one of the entries".to_string()).into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("compare error: {}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("compare error: {}", e)).into_response(),
     }
+}
 
-    let now = Utc::now();
-    let secs = now.timestamp() as i128;
-    let nsec = now.timestamp_subsec_nanos() as i128;
-    let timestamp = secs * 1_000_000_000i128 + nsec;
-    let filename = format!("{}.jpg", timestamp);
-    let out_path = format!("{}/{}", photos_dir, filename);
-    imgbuf.save(&out_path)?;
+async fn serve_photo(State(state): State<Arc<AppState>>, Path(name): Path<String>, Query(p): Query<ProfileParam>) -> Response {
+    if !paths::is_contained(&name) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    let url = format!("/photos/{}", name);
+    let file = store.blobs.resolve(&url).await;
+    let mime = std::path::Path::new(&name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(output::OutputFormat::parse)
+        .map(|f| f.mime_type())
+        .unwrap_or("application/octet-stream");
+    match fs::read(&file).await {
+        Ok(bytes) => match crypto::open(bytes) {
+            Ok(bytes) => ([(header::CONTENT_TYPE, mime)], bytes).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("cannot read photo: {}", e)).into_response(),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Unhandled internal error: {}", e)).into_response(),
+    }
+}
 
-    // Write metadata JSON under data/json (new layout)
-    let meta = Entry { path: format!("/photos/{}", filename), sys: sys, dia: dia, pulse: pulse, temp_c: temp_c, temp_jaw: temp_jaw, temp_room: temp_room, pain: pain, timestamp_nanos: timestamp };
-    let meta_path = paths::json_meta_path(&timestamp.to_string());
-    let meta_json = serde_json::to_string(&meta)?;
-    fs::write(&meta_path, meta_json).await?;
+async fn list_tokens(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.auth.list_tokens().await)
+}
 
-    Ok(out_path)
+#[derive(Deserialize)]
+struct CreateTokenRequest {
+    name: String,
+    #[serde(default = "default_token_scope")]
+    scope: Scope,
+    /// Lifetime in days; the token never expires when omitted
+    expires_in_days: Option<u32>,
 }
 
-async fn delete_entry(Path(ts): Path<String>) -> impl IntoResponse {
-    // Derive file paths from timestamp base
-    let photo = format!("{}/{}.jpg", paths::PHOTOS_DIR, ts);
-    let meta_new = paths::json_meta_path(&ts);
-    let meta_legacy = format!("{}/{}.json", paths::PHOTOS_DIR, ts);
+fn default_token_scope() -> Scope {
+    Scope::Read
+}
 
-    // Helper to ignore NotFound errors
-    async fn rm(p: &str) {
-        if let Err(e) = fs::remove_file(p).await {
-            if e.kind() != std::io::ErrorKind::NotFound {
-                eprintln!("delete failed for {}: {}", p, e);
-            }
+async fn create_token(State(state): State<Arc<AppState>>, principal: Option<Extension<Principal>>, Json(req): Json<CreateTokenRequest>) -> Response {
+    let valid_for = req.expires_in_days.map(|d| chrono::Duration::days(d.into()));
+    match state.auth.create_token(&req.name, req.scope, valid_for).await {
+        Ok((token, secret)) => {
+            audit::record(&actor(principal), Event::new(Action::TokenCreate).after(&token)).await;
+            (StatusCode::CREATED, Json(serde_json::json!({ "token": token, "secret": secret }))).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn revoke_token(State(state): State<Arc<AppState>>, principal: Option<Extension<Principal>>, Path(id): Path<String>) -> Response {
+    match state.auth.revoke_token(&id).await {
+        Ok(true) => {
+            audit::record(&actor(principal), Event::new(Action::TokenRevoke).detail(format!("token {}", id))).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("no token with id {}", id)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("revoke error: {}", e)).into_response(),
+    }
+}
+
+async fn blobs_gc(State(state): State<Arc<AppState>>, Query(p): Query<ProfileParam>) -> Response {
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    match store.blobs.gc().await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("gc error: {}", e)).into_response(),
+    }
+}
+
+async fn blobs_verify(State(state): State<Arc<AppState>>, Query(p): Query<ProfileParam>) -> Response {
+    let store = match state.store(&p) {
+        Ok(s) => s,
+        Err(res) => return res.into_response(),
+    };
+    match store.blobs.verify().await {
+        Ok(report) => {
+            let status = if report.is_clean() { StatusCode::OK } else { StatusCode::CONFLICT };
+            (status, Json(report)).into_response()
         }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("verify error: {}", e)).into_response(),
     }
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    /// Only entry records of this profile
+    profile: Option<String>,
+    entry: Option<String>,
+    action: Option<Action>,
+    /// Most recent records to return; defaults to 100
+    limit: Option<usize>,
+}
 
-    rm(&meta_new).await;
-    rm(&meta_legacy).await;
-    rm(&photo).await;
+/// Audit records, newest first.
+async fn audit_log(Query(q): Query<AuditQuery>) -> Response {
+    match audit::read_all().await {
+        Ok(records) => {
+            let list: Vec<audit::Record> = records
+                .into_iter()
+                .rev()
+                .filter(|r| q.profile.as_ref().is_none_or(|p| r.entry_id.is_some() && r.is_for_profile(p)))
+                .filter(|r| q.entry.as_ref().is_none_or(|id| r.entry_id.as_ref() == Some(id)))
+                .filter(|r| q.action.is_none_or(|a| r.action == a))
+                .take(q.limit.unwrap_or(100))
+                .collect();
+            Json(list).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("audit log error: {:#}", e)).into_response(),
+    }
+}
 
-    StatusCode::NO_CONTENT
+async fn audit_verify() -> Response {
+    match audit::verify().await {
+        Ok(report) => {
+            let status = if report.is_intact() { StatusCode::OK } else { StatusCode::CONFLICT };
+            (status, Json(report)).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("verify error: {}", e)).into_response(),
+    }
 }
@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::IpAddr;
+use std::path::Path;
+use tokio::fs;
+
+use crate::config::TlsConfig;
+use crate::paths;
+
+/// HTTPS settings ready for the listener, plus the certificate's SHA-256 fingerprint so
+/// it can be compared on a phone before trusting it.
+pub struct Tls {
+    pub rustls: RustlsConfig,
+    pub fingerprint: String,
+    pub self_signed: bool,
+}
+
+/// The configured certificate and key, or else a self-signed pair kept under
+/// `<data_root>/tls` so devices only have to accept it once. The pair is regenerated when
+/// the names it should cover change.
+pub async fn load(cfg: &TlsConfig, bind: IpAddr) -> Result<Tls> {
+    let (cert_path, key_path, self_signed) = match (&cfg.cert, &cfg.key) {
+        (Some(cert), Some(key)) => (cert.clone(), key.clone(), false),
+        _ => {
+            ensure_self_signed(&subject_names(cfg, bind)).await?;
+            (paths::tls_dir().join("cert.pem"), paths::tls_dir().join("key.pem"), true)
+        }
+    };
+    let cert_pem = fs::read(&cert_path).await.with_context(|| format!("reading {}", cert_path.display()))?;
+    let key_pem = fs::read(&key_path).await.with_context(|| format!("reading {}", key_path.display()))?;
+    let fingerprint = fingerprint(&cert_pem).with_context(|| format!("reading certificate {}", cert_path.display()))?;
+    let rustls = RustlsConfig::from_pem(cert_pem, key_pem).await.with_context(|| format!("loading {} and {}", cert_path.display(), key_path.display()))?;
+    Ok(Tls { rustls, fingerprint, self_signed })
+}
+
+/// Loopback names, the bind address when it is a specific one, and `tls.hostnames`.
+fn subject_names(cfg: &TlsConfig, bind: IpAddr) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    if !bind.is_unspecified() && !bind.is_loopback() {
+        names.push(bind.to_string());
+    }
+    for n in &cfg.hostnames {
+        let n = n.trim().to_string();
+        if !n.is_empty() && !names.contains(&n) {
+            names.push(n);
+        }
+    }
+    names
+}
+
+async fn ensure_self_signed(names: &[String]) -> Result<()> {
+    let dir = paths::tls_dir();
+    let names_file = dir.join("names.txt");
+    let wanted = names.join("\n");
+    let current = fs::read_to_string(&names_file).await.ok();
+    if current.as_deref() == Some(wanted.as_str()) && fs::try_exists(dir.join("cert.pem")).await? && fs::try_exists(dir.join("key.pem")).await? {
+        return Ok(());
+    }
+    let cert = rcgen::generate_simple_self_signed(names.to_vec()).map_err(|e| anyhow!("generating certificate: {}", e))?;
+    let cert_pem = cert.serialize_pem().map_err(|e| anyhow!("encoding certificate: {}", e))?;
+    fs::create_dir_all(&dir).await?;
+    write_private(&dir.join("key.pem"), cert.serialize_private_key_pem().as_bytes()).await?;
+    fs::write(dir.join("cert.pem"), cert_pem).await?;
+    fs::write(&names_file, wanted).await?;
+    tracing::info!(dir = %dir.display(), names = %names.join(", "), "generated a self-signed certificate");
+    Ok(())
+}
+
+async fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    fs::write(path, bytes).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+    Ok(())
+}
+
+/// SHA-256 of the first certificate in a PEM file, as colon-separated hex.
+fn fingerprint(cert_pem: &[u8]) -> Result<String> {
+    let der = rustls_pemfile::certs(&mut &cert_pem[..])?.into_iter().next().ok_or_else(|| anyhow!("no certificate found"))?;
+    let hex = crate::blobstore::hash_bytes(&der).to_ascii_uppercase();
+    Ok(hex.as_bytes().chunks(2).map(|c| String::from_utf8_lossy(c).into_owned()).collect::<Vec<_>>().join(":"))
+}
@@ -1,9 +1,12 @@
 // Frontend script for Vital Tracker
 
 (async function(){
-  let bpChart = null, pulseChart = null, tempChart = null;
+  let bpChart = null, pulseChart = null, tempChart = null, colorChart = null, derivedChart = null;
   let tableState = { page: 1, pageSize: 10, sortKey: 'timestamp_nanos', sortDir: 'desc' };
   let lastEntries = [];
+  // Category thresholds of the configured blood pressure guideline (from /bp/guideline)
+  let bpGuideline = null;
+  const bpBandColors = { normal:'rgba(46,160,67,.08)', elevated:'rgba(240,200,0,.12)', stage_1:'rgba(255,140,0,.12)', stage_2:'rgba(220,20,60,.12)', crisis:'rgba(128,0,0,.18)' };
 
   let video = null; 
   // Thumbnails and hidden temporary canvas used for captures
@@ -12,6 +15,47 @@
   let captureStep = 0; // 0=front,1=left,2=right,3=neck,4=done
   let capturedBlobs = { front: null, left: null, right: null, neck: null };
 
+  // Profile (person) whose entries are shown; empty means the server's default
+  let profile = localStorage.getItem('vital_profile') || '';
+  function withProfile(url){
+    if(!profile) return url;
+    return url + (url.includes('?') ? '&' : '?') + 'profile=' + encodeURIComponent(profile);
+  }
+
+  // fetch() scoped to the selected profile that sends the browser back to the login form
+  // once the session has expired
+  async function api(url, opts){
+    const r = await fetch(withProfile(url), opts);
+    if(r.status === 401){ location.href = '/login'; }
+    return r;
+  }
+  function entryId(e){ return e.id || (e.path||'').split('/').pop().replace(/\.[^.]+$/,'') || String(e.timestamp_nanos||''); }
+  // Same rule as the server: the highest band whose systolic or diastolic bound is reached
+  function classifyBp(sys, dia){
+    if(!bpGuideline || !(sys > 0 && dia > 0)) return null;
+    const reaches = (v, bound, exclusive)=> bound != null && (exclusive ? v > bound : v >= bound);
+    const band = bpGuideline.bands.slice().reverse().find(b=> reaches(sys, b.sys, b.exclusive) || reaches(dia, b.dia, b.exclusive));
+    return band ? { category: band.category, label: band.label } : { category:'normal', label:'Normal' };
+  }
+  // Shades the BP chart by the guideline's systolic bands
+  const bpBandsPlugin = {
+    id: 'bpBands',
+    beforeDatasetsDraw(chart){
+      const y = chart.scales.y; const area = chart.chartArea;
+      if(!bpGuideline || !y || !area) return;
+      const bounds = [{ category:'normal', from: -Infinity }].concat(bpGuideline.bands.filter(b=> b.sys != null).map(b=> ({ category: b.category, from: b.sys })));
+      const ctx = chart.ctx; ctx.save();
+      bounds.forEach((b, i)=>{
+        const to = i + 1 < bounds.length ? bounds[i+1].from : Infinity;
+        const top = Math.max(area.top, y.getPixelForValue(Math.min(to, y.max)));
+        const bottom = Math.min(area.bottom, y.getPixelForValue(Math.max(b.from, y.min)));
+        if(bottom <= top) return;
+        ctx.fillStyle = bpBandColors[b.category] || 'transparent';
+        ctx.fillRect(area.left, top, area.right - area.left, bottom - top);
+      });
+      ctx.restore();
+    }
+  };
   function tsToDate(ts_nanos){ if(!ts_nanos) return null; return new Date(Math.floor(Number(ts_nanos)/1e6)); }
 
   async function loadEntries(){
@@ -21,10 +65,15 @@
     if(gallery) gallery.innerHTML = '';
 
     try{
-      const r = await fetch('/entries');
+      if(!bpGuideline){
+        const g = await api('/bp/guideline');
+        if(g.ok) bpGuideline = await g.json();
+      }
+      const r = await api('/entries');
       if(!r.ok) throw new Error('HTTP '+r.status);
       const list = await r.json();
   const parsed = list.map(e => ({
+    id: e.id,
     path: e.path,
     sys: Number(e.sys||0),
     dia: Number(e.dia||0),
@@ -34,7 +83,15 @@
     temp_jaw: (e.temp_jaw === null || e.temp_jaw === undefined) ? undefined : Number(e.temp_jaw),
     temp_room: (e.temp_room === null || e.temp_room === undefined) ? undefined : Number(e.temp_room),
     pain: (e.pain === null || e.pain === undefined) ? undefined : Number(e.pain),
-    timestamp_nanos: Number(e.timestamp_nanos||0)
+    // { category, label } under the configured guideline
+    bp_class: e.bp_class || null,
+    // Mean arterial pressure, pulse pressure and shock index, computed by the server
+    map: e.map == null ? undefined : Number(e.map),
+    pulse_pressure: e.pulse_pressure == null ? undefined : Number(e.pulse_pressure),
+    shock_index: e.shock_index == null ? undefined : Number(e.shock_index),
+    timestamp_nanos: Number(e.timestamp_nanos||0),
+    // Per-view region-of-interest colour ({ neck: { l, a, b, redness }, ... })
+    color: e.color || {}
   }));
       parsed.sort((a,b)=> b.timestamp_nanos - a.timestamp_nanos);
   // Cache for exports and other controls
@@ -52,14 +109,9 @@
         for(const e of pageItems){
           const tr = document.createElement('tr');
           const date = tsToDate(e.timestamp_nanos);
-          // Derive base id from photo path to avoid JS Number precision issues on timestamp_nanos
-          const tsBase = (()=>{
-            try{
-              const fname = (e.path||'').split('/').pop()||'';
-              return fname.replace(/\.jpg$/i,'');
-            }catch(_){ return String(e.timestamp_nanos||''); }
-          })();
-          tr.innerHTML = `<td>${date?date.toLocaleString():''}</td><td>${e.sys}</td><td>${e.dia}</td><td>${e.pulse}</td><td>${e.temp_c}</td><td>${e.temp_jaw??''}</td><td>${e.temp_room??''}</td><td>${e.pain??''}</td><td><a target="_blank" href="${e.path}">photo</a></td><td><button class="del-btn" data-ts="${tsBase}">Delete</button></td>`;
+          // Exact id from the server (timestamp_nanos loses precision as a JS Number)
+          const tsBase = entryId(e);
+          tr.innerHTML = `<td>${date?date.toLocaleString():''}</td><td>${e.sys}</td><td>${e.dia}</td><td class="bp-${e.bp_class?.category||''}">${e.bp_class?.label||''}</td><td>${e.pulse}</td><td>${e.temp_c}</td><td>${e.temp_jaw??''}</td><td>${e.temp_room??''}</td><td>${e.pain??''}</td><td>${e.path ? `<a target="_blank" href="${withProfile(e.path)}">photo</a>` : ''}</td><td><button class="del-btn" data-ts="${tsBase}">Delete</button></td>`;
           tbody.appendChild(tr);
         }
         // Attach delete handlers
@@ -69,7 +121,7 @@
             if(!ts) return;
             if(!confirm('Delete this entry?')) return;
             try{
-              const r = await fetch(`/entry/${encodeURIComponent(ts)}`, { method:'DELETE' });
+              const r = await api(`/entry/${encodeURIComponent(ts)}`, { method:'DELETE' });
               if(r.ok){
                 await loadEntries();
               } else {
@@ -80,11 +132,24 @@
         });
       }
 
+      // Comparison pickers (newest first); keep the current selection across reloads
+      for(const [id, idx] of [['cmp_a', Math.min(1, parsed.length-1)], ['cmp_b', 0]]){
+        const sel = document.getElementById(id); if(!sel) continue;
+        const prev = sel.value; sel.innerHTML = '';
+        parsed.forEach((e, i)=>{
+          const opt = document.createElement('option');
+          opt.value = entryId(e);
+          opt.textContent = tsToDate(e.timestamp_nanos)?.toLocaleString() || opt.value;
+          if(prev ? opt.value === prev : i === idx) opt.selected = true;
+          sel.appendChild(opt);
+        });
+      }
+
       // Photo Gallery
       if(gallery){
-        for(const e of parsed){
+        for(const e of parsed.filter(e => e.path)){
           const div = document.createElement('div');
-          const img = document.createElement('img'); img.src = e.path; img.style.maxWidth = '200px'; img.style.margin = '6px';
+          const img = document.createElement('img'); img.src = withProfile(e.path); img.style.maxWidth = '200px'; img.style.margin = '6px';
           const meta = document.createElement('div'); meta.textContent = tsToDate(e.timestamp_nanos) ? tsToDate(e.timestamp_nanos).toLocaleString() : '';
           div.appendChild(meta); div.appendChild(img); gallery.appendChild(div);
         }
@@ -222,9 +287,12 @@
           sysData = asc.map(x=>x.sys); diaData = asc.map(x=>x.dia); pulseData = asc.map(x=>x.pulse); painData = asc.map(x=> (x.pain ?? null)); tempData = asc.map(x=>x.temp_c);
           const mean = arr => arr.length? (arr.reduce((s,v)=> s + Number(v||0),0)/arr.length):0;
           const sysAvg = mean(sysData), diaAvg = mean(diaData), pulseAvg = mean(pulseData), tempAvg = mean(tempData);
+          // Classify the average of entries that have readings only
+          const measured = asc.filter(x=> x.sys > 0 && x.dia > 0);
+          const avgClass = classifyBp(mean(measured.map(x=> x.sys)), mean(measured.map(x=> x.dia)));
           const line = (v)=> labels.map(()=> v);
           extraDatasets = [
-            { label:'SYS avg', data: line(sysAvg), borderColor:'rgba(220,20,60,.5)', borderDash:[6,4], fill:false },
+            { label:`SYS avg (${avgClass?.label || '-'})`, data: line(sysAvg), borderColor:'rgba(220,20,60,.5)', borderDash:[6,4], fill:false },
             { label:'DIA avg', data: line(diaAvg), borderColor:'rgba(30,144,255,.5)', borderDash:[6,4], fill:false }
           ];
           // For pulse and temp charts we'll add their own avg lines separately
@@ -236,6 +304,8 @@
         if(bpChart){ bpChart.destroy(); bpChart = null; }
         if(pulseChart){ pulseChart.destroy(); pulseChart = null; }
         if(tempChart){ tempChart.destroy(); tempChart = null; }
+        if(colorChart){ colorChart.destroy(); colorChart = null; }
+        if(derivedChart){ derivedChart.destroy(); derivedChart = null; }
 
         const bpCtx = document.getElementById('bpChart').getContext('2d');
         const bpDatasets = [
@@ -246,9 +316,15 @@
         bpChart = new Chart(bpCtx, {
           type:'line',
           data:{ labels, datasets: bpDatasets },
+          plugins:[bpBandsPlugin],
           options:{
             responsive:true,
             plugins:{
+              // Category of the reading, or of the average in the aggregated views
+              tooltip:{ callbacks:{ footer: items=> {
+                const i = items[0]?.dataIndex; const c = i == null ? null : classifyBp(sysData[i], diaData[i]);
+                return c ? `${c.label} (${bpGuideline.name})` : '';
+              } } },
               zoom:{
                 zoom:{ wheel:{ enabled:true }, pinch:{ enabled:true }, mode:'x', drag:{ enabled:true, borderColor:'rgba(0,0,0,.3)', backgroundColor:'rgba(0,0,0,.08)' } },
                 pan:{ enabled:true, mode:'x' }
@@ -345,6 +421,67 @@
             }
           }
         });
+
+        // Redness (Lab a*) per tracked view; only entries that were measured, so no aggregation
+        const colorCanvas = document.getElementById('colorChart');
+        if(colorCanvas){
+          let measured = parsed.slice().sort((a,b)=> a.timestamp_nanos - b.timestamp_nanos).filter(e=> Object.keys(e.color).length);
+          if(view === '30d'){ const cutoff = Date.now() - 30*24*60*60*1000; measured = measured.filter(e=> toMs(e.timestamp_nanos) >= cutoff); }
+          const colorLabels = measured.map(x=> fmt(tsToDate(x.timestamp_nanos)));
+          const viewColors = { front:'rgb(128,128,128)', left:'rgb(220,20,60)', right:'rgb(255,140,0)', neck:'rgb(199,21,133)' };
+          const colorViews = Array.from(new Set(measured.flatMap(e=> Object.keys(e.color))));
+          const colorDatasets = colorViews.map(v=> ({ label: v.charAt(0).toUpperCase() + v.slice(1) + ' a*', data: measured.map(e=> e.color[v] ? Number(e.color[v].a) : null), borderColor: viewColors[v] || 'rgb(0,0,0)', fill:false, spanGaps:true }));
+          colorChart = new Chart(colorCanvas.getContext('2d'), {
+            type:'line',
+            data:{ labels: colorLabels, datasets: colorDatasets },
+            options:{
+              responsive:true,
+              plugins:{
+                zoom:{
+                  zoom:{ wheel:{ enabled:true }, pinch:{ enabled:true }, mode:'x', drag:{ enabled:true, borderColor:'rgba(0,0,0,.3)', backgroundColor:'rgba(0,0,0,.08)' } },
+                  pan:{ enabled:true, mode:'x' }
+                }
+              }
+            }
+          });
+        }
+
+        // MAP, pulse pressure and shock index; the aggregated views derive them from the averages
+        const derivedCanvas = document.getElementById('derivedChart');
+        if(derivedCanvas){
+          let mapData, ppData, siData;
+          if(view === '7d_weekly' || view === 'daily' || view === 'tod'){
+            // Same rules as the server: nothing without both pressures, no shock index without a pulse
+            const both = (a, b, f)=> a.map((v, i)=> (v > 0 && b[i] > 0) ? f(Number(v), Number(b[i])) : null);
+            mapData = both(sysData, diaData, (s, d)=> d + (s - d) / 3);
+            ppData = both(sysData, diaData, (s, d)=> s - d);
+            siData = both(pulseData, sysData, (p, s)=> p / s);
+          } else {
+            mapData = asc.map(x=> x.map ?? null); ppData = asc.map(x=> x.pulse_pressure ?? null); siData = asc.map(x=> x.shock_index ?? null);
+          }
+          derivedChart = new Chart(derivedCanvas.getContext('2d'), {
+            type:'line',
+            data:{ labels, datasets: [
+              { label:'MAP', data: mapData, borderColor:'rgb(128,0,128)', yAxisID:'y', fill:false, spanGaps:true },
+              { label:'Pulse pressure', data: ppData, borderColor:'rgb(0,128,128)', yAxisID:'y', fill:false, spanGaps:true },
+              { label:'Shock index', data: siData, borderColor:'rgb(255,140,0)', yAxisID:'ySi', fill:false, spanGaps:true }
+            ] },
+            options:{
+              responsive:true,
+              interaction: { mode:'nearest', intersect:false },
+              scales: {
+                y: { type:'linear', position:'left', title:{ display:true, text:'mmHg' } },
+                ySi: { type:'linear', position:'right', grid:{ drawOnChartArea:false }, suggestedMin:0, suggestedMax:1 }
+              },
+              plugins:{
+                zoom:{
+                  zoom:{ wheel:{ enabled:true }, pinch:{ enabled:true }, mode:'x', drag:{ enabled:true, borderColor:'rgba(0,0,0,.3)', backgroundColor:'rgba(0,0,0,.08)' } },
+                  pan:{ enabled:true, mode:'x' }
+                }
+              }
+            }
+          });
+        }
       }
 
     } catch(err){
@@ -357,10 +494,10 @@
   // CSV export
   function exportCsv(arr, filename){
     if(!arr || !arr.length){ alert('No data to export'); return; }
-  const cols = ['timestamp_nanos','sys','dia','pulse','temp_c','temp_jaw','temp_room','pain','path'];
+  const cols = ['timestamp_nanos','sys','dia','bp_class','pulse','map','pulse_pressure','shock_index','temp_c','temp_jaw','temp_room','pain','path'];
     const header = cols.join(',');
     const lines = arr.map(r=> cols.map(c=>{
-      let v = r[c];
+      let v = c === 'bp_class' ? r.bp_class?.category : r[c];
       if(v === undefined || v === null) v = '';
       if(typeof v === 'string') v = '"'+String(v).replace(/"/g,'""')+'"';
       return v;
@@ -396,6 +533,41 @@
       exportCsv(lastEntries, 'vital_all.csv');
     });
 
+    // Time-lapse of a single view across entries
+    document.getElementById('tl_render')?.addEventListener('click', async ()=>{
+      const status = document.getElementById('tl_status'); const out = document.getElementById('tl_result');
+      const params = new URLSearchParams();
+      params.set('view', document.getElementById('tl_view')?.value || 'neck');
+      const from = document.getElementById('tl_from')?.value; if(from) params.set('from', from);
+      const to = document.getElementById('tl_to')?.value; if(to) params.set('to', to);
+      params.set('fps', document.getElementById('tl_fps')?.value || '2');
+      params.set('overlay', document.getElementById('tl_overlay')?.checked ? 'true' : 'false');
+      if(status) status.textContent = 'Rendering...';
+      try{
+        const r = await api('/timelapse?' + params.toString());
+        if(!r.ok){ if(status) status.textContent = 'Error: ' + await r.text(); return; }
+        const blob = await r.blob();
+        if(out){ if(out.src) URL.revokeObjectURL(out.src); out.src = URL.createObjectURL(blob); out.style.display = 'block'; }
+        if(status) status.textContent = '';
+      } catch(e){ if(status) status.textContent = 'Network error: ' + e; }
+    });
+
+    // Side-by-side comparison of two entries
+    document.getElementById('cmp_render')?.addEventListener('click', async ()=>{
+      const status = document.getElementById('cmp_status'); const out = document.getElementById('cmp_result');
+      const a = document.getElementById('cmp_a')?.value; const b = document.getElementById('cmp_b')?.value;
+      if(!a || !b){ if(status) status.textContent = 'Pick two entries'; return; }
+      const params = new URLSearchParams({ a, b, view: document.getElementById('cmp_view')?.value || '' });
+      if(status) status.textContent = 'Rendering...';
+      try{
+        const r = await api('/compare?' + params.toString());
+        if(!r.ok){ if(status) status.textContent = 'Error: ' + await r.text(); return; }
+        const blob = await r.blob();
+        if(out){ if(out.src) URL.revokeObjectURL(out.src); out.src = URL.createObjectURL(blob); out.style.display = 'block'; }
+        if(status) status.textContent = '';
+      } catch(e){ if(status) status.textContent = 'Network error: ' + e; }
+    });
+
     // Graph view select (may be injected) - attach here so it works after injection
     const graphSelect = document.getElementById('graph_view_select');
     if(graphSelect) graphSelect.addEventListener('change', ()=> loadEntries());
@@ -407,11 +579,15 @@
     const bpCard = document.getElementById('bpChart')?.parentElement;
     const pulseCard = document.getElementById('pulseChart')?.parentElement;
     const tempCard = document.getElementById('tempChart')?.parentElement;
+    const colorCard = document.getElementById('colorChart')?.parentElement;
+    const derivedCard = document.getElementById('derivedChart')?.parentElement;
     function showTab(tab){
       if(!bpCard || !pulseCard || !tempCard) return;
       bpCard.style.display = (tab==='bp')? 'block':'none';
       pulseCard.style.display = (tab==='pulse')? 'block':'none';
       tempCard.style.display = (tab==='temp')? 'block':'none';
+      if(colorCard) colorCard.style.display = (tab==='color')? 'block':'none';
+      if(derivedCard) derivedCard.style.display = (tab==='derived')? 'block':'none';
       tabs.forEach(t=> t.classList.toggle('active', t.getAttribute('data-tab')===tab));
     }
     tabs.forEach(t=> t.addEventListener('click', ()=> showTab(t.getAttribute('data-tab'))));
@@ -451,6 +627,9 @@
   else btn.textContent = 'Submit Entry';
   }
 
+  // /entry answers "ok" plus one "warning: ..." line per photo quality problem
+  function qualityWarnings(body){ return String(body||'').split('\n').filter(l=> l.startsWith('warning: ')).map(l=> l.slice(9)); }
+
   // Submit using captured blobs
   async function doSubmitWithCaptured(){
     const status = document.getElementById('status'); if(status) status.textContent = 'Uploading...';
@@ -460,8 +639,11 @@
   if(capturedBlobs.right) fd.append('photo_right', new File([capturedBlobs.right], 'right.png', { type:'image/png' }));
   if(capturedBlobs.neck) fd.append('photo_neck', new File([capturedBlobs.neck], 'neck.png', { type:'image/png' }));
     try{
-      const res = await fetch('/entry',{ method:'POST', body: fd });
+      const res = await api('/entry',{ method:'POST', body: fd });
       if(res.ok){
+        // Keep the window open when the server flagged photo quality problems
+        const warnings = qualityWarnings(await res.text());
+        if(warnings.length){ status && (status.textContent = 'Saved with warnings: ' + warnings.join('; ')); return; }
         status && (status.textContent='Saved');
         try{ document.title = 'VITAL_OK_CLOSE'; }catch(_){ }
         // Attempt to close the window if this tab was opened by our hotkey launcher
@@ -500,12 +682,14 @@
     if(combinedBlob) fd.append('photo_combined', new File([combinedBlob], 'combined.png', { type:'image/png' }));
 
     try{
-      const res = await fetch('/entry',{ method:'POST', body: fd });
+      const res = await api('/entry',{ method:'POST', body: fd });
       if(res.ok){
+        const warnings = qualityWarnings(await res.text());
         status && (status.textContent='Saved');
-        try{ document.title = 'VITAL_OK_CLOSE'; }catch(_){ }
         
   captureStep = 0; capturedBlobs = { front:null,left:null,right:null,neck:null }; const statusEl = document.getElementById('cap_status'); if(statusEl) statusEl.textContent = '0 / 4 captured'; const btn = document.getElementById('cap_cycle'); if(btn) { btn.disabled = false; updateCaptureButton(); }
+        if(warnings.length){ status && (status.textContent = 'Saved with warnings: ' + warnings.join('; ')); return; }
+        try{ document.title = 'VITAL_OK_CLOSE'; }catch(_){ }
 
         setTimeout(()=>{
           try{ if(window.close){ window.close(); } }catch(_){}
@@ -527,6 +711,34 @@
 
   window.vital_loadEntries = loadEntries;
 
+  // Profile switcher, only shown when more than one person is configured
+  async function setupProfiles(){
+    const sel = document.getElementById('profile_select'); if(!sel) return;
+    let list = [];
+    try{
+      const r = await api('/profiles');
+      if(r.ok) list = await r.json();
+    } catch(e){ console.warn('loading profiles failed', e); }
+    if(profile && !list.some(p => p.id === profile)){
+      profile = ''; localStorage.removeItem('vital_profile');
+      loadEntries();
+    }
+    sel.innerHTML = '';
+    for(const p of list){
+      const opt = document.createElement('option');
+      opt.value = p.id; opt.textContent = p.name;
+      if(p.id === (profile || list[0].id)) opt.selected = true;
+      sel.appendChild(opt);
+    }
+    sel.hidden = list.length < 2;
+    sel.addEventListener('change', ()=>{
+      profile = sel.value; localStorage.setItem('vital_profile', profile);
+      tableState.page = 1;
+      loadEntries();
+    });
+  }
+  setupProfiles();
+
   // Wrap the binder so it initializes capture button and camera when DOM is ready
   (function(){
     const orig = window.vital_bindControls;
@@ -563,3 +775,27 @@
     };
   })();
 })();
+
+// View switching for the nav links (kept out of index.html so the CSP needs no inline scripts)
+document.addEventListener('DOMContentLoaded', ()=>{
+  const links = document.querySelectorAll('nav a[data-view]');
+  function showView(v){
+    document.querySelectorAll('.view-section').forEach(el=> el.style.display = 'none');
+    if(v === 'quick'){
+      document.getElementById('quick').style.display = 'block';
+    } else {
+      document.getElementById('quick').style.display = 'none';
+      const el = document.getElementById('view-'+v);
+      if(el) el.style.display = 'block';
+    }
+    try{ if(window.vital_loadEntries) window.vital_loadEntries(); }catch(e){}
+  }
+  links.forEach(a=> a.addEventListener('click', (e)=>{ e.preventDefault(); const v = a.getAttribute('data-view'); showView(v); }));
+
+  // Bind controls when all elements are present
+  try{ if(window.vital_bindControls) window.vital_bindControls(); }catch(e){ console.warn('bind failed', e); }
+  try{ if(window.vital_loadEntries) window.vital_loadEntries(); }catch(e){ console.warn('loadEntries failed', e); }
+
+  // Always default to Quick Entry on load; do not persist last view
+  showView('quick');
+});
@@ -0,0 +1,4 @@
+// Shows the error line after a rejected password (the server redirects back with ?failed=1)
+if(new URLSearchParams(location.search).has('failed')){
+  document.getElementById('login_failed').hidden = false;
+}
@@ -0,0 +1,97 @@
+//! Runs the built binary against throwaway data roots, as the command line is what moves
+//! entries between installations.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vital-tracker-export-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs the binary in `dir` with `root` as data root; `dir` holds no config file.
+fn run(dir: &Path, root: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vital-tracker"))
+        .current_dir(dir)
+        .env_remove("VITAL_CONFIG")
+        .env_remove("VITAL_PROFILES")
+        .env("VITAL_DISABLE_INFLUX", "1")
+        .env("VITAL_LOG_FILE", "0")
+        .args(["--data-root", root])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn ok(out: Output) -> Output {
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    out
+}
+
+fn stored_entry(root: &Path, entry: serde_json::Value) {
+    let json = root.join("json");
+    std::fs::create_dir_all(&json).unwrap();
+    std::fs::write(json.join(format!("{}.json", entry["timestamp_nanos"])), entry.to_string()).unwrap();
+}
+
+/// An entry from before readings were required, with its photo in the legacy layout.
+fn legacy_entry(root: &Path) {
+    stored_entry(root, serde_json::json!({ "path": "/photos/1600000000000000000.jpg", "sys": 0, "dia": 0, "pulse": 0, "temp_c": 0.0, "timestamp_nanos": 1600000000000000000u64 }));
+    std::fs::create_dir_all(root.join("photos")).unwrap();
+    std::fs::write(root.join("photos/1600000000000000000.jpg"), b"legacy photo").unwrap();
+}
+
+fn entries(path: &Path) -> Vec<serde_json::Value> {
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn export_then_import_round_trips() {
+    let dir = temp_dir();
+    legacy_entry(&dir.join("a"));
+    let photo = image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x ^ y) * 8) as u8]));
+    photo.save(dir.join("neck.png")).unwrap();
+    ok(run(&dir, "a", &["add", "--sys", "128", "--dia", "82", "--pulse", "71", "--temp", "36.8", "--neck", "neck.png"]));
+
+    ok(run(&dir, "a", &["export", "-o", "first.json", "--photos-dir", "photos"]));
+    let first = entries(&dir.join("first.json"));
+    assert_eq!(first.len(), 2);
+    assert!(first[0]["bp_class"].is_null(), "{}", first[0]);
+
+    let out = ok(run(&dir, "b", &["import", "first.json", "--photos-dir", "photos"]));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Imported 2 entries, 0 already present, 0 skipped"));
+    ok(run(&dir, "b", &["export", "-o", "second.json"]));
+    assert_eq!(entries(&dir.join("second.json")), first);
+    ok(run(&dir, "b", &["verify"]));
+
+    // Importing again leaves everything as it is
+    let out = ok(run(&dir, "b", &["import", "first.json", "--photos-dir", "photos"]));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("Imported 0 entries, 2 already present"));
+}
+
+#[test]
+fn unsafe_photo_paths_are_refused() {
+    let dir = temp_dir();
+    let root = dir.join("a");
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("auth.json"), b"secret").unwrap();
+    stored_entry(&root, serde_json::json!({ "path": "/photos/../auth.json", "sys": 0, "dia": 0, "pulse": 0, "temp_c": 0.0, "timestamp_nanos": 1 }));
+
+    // Export skips the photo instead of copying the file it points at
+    let out = ok(run(&dir, "a", &["export", "-o", "out.json", "--photos-dir", "export/photos"]));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("unsafe photo path"));
+    assert!(!dir.join("export/auth.json").exists());
+
+    // Import refuses such entries whether or not photos are copied
+    for bad in ["/photos/../auth.json", "/photos/..\\auth.json", "/etc/passwd"] {
+        let entry = serde_json::json!([{ "path": "", "views": { "neck": bad }, "sys": 120, "dia": 80, "pulse": 60, "temp_c": 36.5, "timestamp_nanos": 2 }]);
+        std::fs::write(dir.join("bad.json"), entry.to_string()).unwrap();
+        for args in [&["import", "bad.json"][..], &["import", "bad.json", "--photos-dir", "export/photos"]] {
+            let out = run(&dir, "b", args);
+            assert!(!out.status.success());
+            assert!(String::from_utf8_lossy(&out.stderr).contains("unsafe photo path"), "{}", String::from_utf8_lossy(&out.stderr));
+        }
+    }
+    assert!(!dir.join("b/json/2.json").exists());
+}